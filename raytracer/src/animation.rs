@@ -0,0 +1,228 @@
+use crate::camera::Camera;
+use crate::camera::Viewer;
+use crate::ray::Ray;
+use crate::tools;
+use crate::vec3::Vec3;
+
+// camera settings at one moment, times in seconds like the object keyframes
+#[derive(Debug, Clone, Copy)]
+pub struct CameraKey {
+    pub time: f64,
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+}
+
+impl CameraKey {
+    pub fn new(
+        time: f64,
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vfov: f64,
+        aperture: f64,
+        focus_dist: f64,
+    ) -> Self {
+        Self {
+            time,
+            lookfrom,
+            lookat,
+            vfov,
+            aperture,
+            focus_dist,
+        }
+    }
+
+    fn lerp(a: &Self, b: &Self, t: f64) -> Self {
+        let mix = |x: f64, y: f64| x + (y - x) * t;
+        Self::new(
+            mix(a.time, b.time),
+            a.lookfrom + (b.lookfrom - a.lookfrom) * t,
+            a.lookat + (b.lookat - a.lookat) * t,
+            mix(a.vfov, b.vfov),
+            mix(a.aperture, b.aperture),
+            mix(a.focus_dist, b.focus_dist),
+        )
+    }
+}
+
+const ORBIT_KEYS: usize = 64;
+
+// keys sorted by time, held before the first and after the last
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    pub keys: Vec<CameraKey>,
+    pub vup: Vec3,
+}
+
+impl CameraPath {
+    pub fn new(keys: Vec<CameraKey>, vup: Vec3) -> Self {
+        assert!(!keys.is_empty(), "a camera path needs at least one key");
+        let mut keys = keys;
+        keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        Self { keys, vup }
+    }
+
+    // one turn around lookat over the duration, the usual turntable
+    pub fn orbit(lookat: Vec3, radius: f64, height: f64, vfov: f64, duration: f64) -> Self {
+        let mut keys: Vec<CameraKey> = Vec::new();
+        for i in 0..=ORBIT_KEYS {
+            let a: f64 = i as f64 / ORBIT_KEYS as f64;
+            let phi: f64 = 2.0 * tools::PI * a;
+            let lookfrom = lookat + Vec3::new(radius * phi.sin(), height, radius * phi.cos());
+            let dist: f64 = (lookfrom - lookat).length();
+            keys.push(CameraKey::new(
+                a * duration,
+                lookfrom,
+                lookat,
+                vfov,
+                0.0,
+                dist,
+            ));
+        }
+        Self::new(keys, Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    pub fn at(&self, time: f64) -> CameraKey {
+        let first: CameraKey = self.keys[0];
+        let last: CameraKey = *self.keys.last().unwrap();
+        if time <= first.time {
+            return first;
+        }
+        if time >= last.time {
+            return last;
+        }
+        let mut i = 0;
+        while self.keys[i + 1].time < time {
+            i += 1;
+        }
+        let (a, b) = (&self.keys[i], &self.keys[i + 1]);
+        CameraKey::lerp(a, b, (time - a.time) / (b.time - a.time))
+    }
+
+    // the camera where it stands halfway through an exposure from tm0 to tm1
+    pub fn camera(&self, aspect_ratio: f64, tm0: f64, tm1: f64) -> Camera {
+        let k: CameraKey = self.at((tm0 + tm1) / 2.0);
+        Camera::new(
+            k.lookfrom,
+            k.lookat,
+            self.vup,
+            k.vfov,
+            aspect_ratio,
+            k.aperture,
+            k.focus_dist,
+            tm0,
+            tm1,
+        )
+    }
+}
+
+// a frame's camera, posed along the path at the time each ray leaves, so a
+// moving camera blurs over the shutter like moving objects do
+#[derive(Debug, Clone)]
+pub struct FrameCamera {
+    path: CameraPath,
+    aspect_ratio: f64,
+    tm0: f64,
+    tm1: f64,
+}
+
+impl Viewer for FrameCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Ray {
+        let time: f64 = tools::randf(self.tm0, self.tm1);
+        let cam: Camera = self.path.camera(self.aspect_ratio, time, time);
+        cam.get_ray_at(s, t, time)
+    }
+}
+
+// a numbered image sequence; frame i opens its shutter at i / fps and keeps
+// it open for the given fraction of a frame, 0.5 being a 180 degree shutter
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub camera: CameraPath,
+    pub frames: i32,
+    pub fps: f64,
+    pub shutter: f64,
+    pub static_scene: bool, // only the camera moves, so one BVH serves every frame
+}
+
+impl Animation {
+    pub fn new(camera: CameraPath, frames: i32, fps: f64, static_scene: bool) -> Self {
+        Self {
+            camera,
+            frames,
+            fps,
+            shutter: 0.5,
+            static_scene,
+        }
+    }
+
+    pub fn shutter(&self, frame: i32) -> (f64, f64) {
+        let tm0: f64 = frame as f64 / self.fps;
+        (tm0, tm0 + self.shutter / self.fps)
+    }
+    // from the first shutter opening to the last closing
+    pub fn span(&self) -> (f64, f64) {
+        (self.shutter(0).0, self.shutter(self.frames - 1).1)
+    }
+
+    pub fn camera(&self, frame: i32, aspect_ratio: f64) -> FrameCamera {
+        let (tm0, tm1) = self.shutter(frame);
+        FrameCamera {
+            path: self.camera.clone(),
+            aspect_ratio,
+            tm0,
+            tm1,
+        }
+    }
+
+    pub fn path(frame: i32) -> String {
+        format!("output/frame_{:04}.png", frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let path = CameraPath::new(
+            vec![
+                CameraKey::new(2.0, Vec3::new(0.0, 0.0, 4.0), Vec3::zero(), 60.0, 0.0, 4.0),
+                CameraKey::new(0.0, Vec3::zero(), Vec3::zero(), 40.0, 0.0, 1.0),
+            ],
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        assert_eq!(path.keys[0].time, 0.0);
+        let k = path.at(0.5);
+        assert!((k.lookfrom.z() - 1.0).abs() < 1e-9 && (k.vfov - 45.0).abs() < 1e-9);
+        assert_eq!(path.at(-1.0).vfov, 40.0);
+        assert_eq!(path.at(3.0).vfov, 60.0);
+    }
+
+    #[test]
+    fn test_camera_per_ray() {
+        // a fast orbit, an eighth of a turn while the shutter is open
+        let anim = Animation::new(
+            CameraPath::orbit(Vec3::zero(), 5.0, 0.0, 40.0, 4.0),
+            4,
+            1.0,
+            true,
+        );
+        let cam = anim.camera(1, 1.0);
+        let (tm0, tm1) = anim.shutter(1);
+        let mut spread: f64 = 0.0;
+        for _ in 0..100 {
+            let r = cam.get_ray(0.5, 0.5);
+            assert!(r.time() >= tm0 && r.time() < tm1);
+            // the centre ray leaves from where the path stands at its time
+            let from = anim.camera.at(r.time()).lookfrom;
+            assert!((r.origin() - from).length() < 1e-9);
+            assert!((r.diraction().unit() + from.unit()).length() < 1e-9);
+            spread = spread.max((from - anim.camera.at(tm0).lookfrom).length());
+        }
+        assert!(spread > 1.0);
+    }
+}
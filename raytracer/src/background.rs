@@ -0,0 +1,433 @@
+use crate::color;
+use crate::color::Color;
+use crate::spectrum;
+use crate::tools;
+use crate::vec3::Onb;
+use crate::vec3::Vec3;
+use image::codecs::hdr::HdrDecoder;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+// radiance arriving from infinitely far away, seen by rays that escape the scene
+pub trait Background: Debug + Send + Sync {
+    fn value(&self, dir: &Vec3) -> Color;
+    // pick a direction towards the background for light sampling,
+    // returning the direction, its radiance and the solid angle pdf
+    fn sample(&self) -> Option<(Vec3, Color, f64)> {
+        None
+    }
+    fn pdf(&self, dir: &Vec3) -> f64 {
+        0.0
+    }
+}
+
+// a constant color, what every scene used before
+impl Background for Color {
+    fn value(&self, dir: &Vec3) -> Color {
+        *self
+    }
+}
+
+// piecewise constant distribution over [0, 1)
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+        let integral: f64 = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+    // continuous sample in [0, 1), its density and the bucket it fell in
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.func.len();
+        let mut i = match self.cdf.binary_search_by(|c| c.partial_cmp(&u).unwrap()) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        i = i.min(n - 1);
+        let width: f64 = self.cdf[i + 1] - self.cdf[i];
+        let du: f64 = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        ((i as f64 + du) / n as f64, self.pdf(i), i)
+    }
+    pub fn pdf(&self, i: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[i] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+// equirectangular (lat-long) environment map, u follows the azimuth like
+// Sphere's uv and the top row of the image is straight up
+#[derive(Debug, Clone)]
+pub struct EnvMap {
+    data: Vec<Color>,
+    width: usize,
+    height: usize,
+    rotation: f64, // about the y axis, radians
+    intensity: f64,
+    rows: Distribution1D,
+    cols: Vec<Distribution1D>,
+}
+
+impl EnvMap {
+    // rotation in degrees about y; .hdr files keep their linear radiance,
+    // other formats are read like ImageTexture. fails with the file's name
+    // when it is missing or cannot be decoded
+    pub fn new(path: &Path, rotation: f64, intensity: f64) -> Result<Self, String> {
+        let fail = |e: &dyn std::fmt::Display| format!("cannot read {}: {}", path.display(), e);
+        let is_hdr = match path.extension() {
+            Some(ext) => ext.to_ascii_lowercase() == "hdr",
+            None => false,
+        };
+        let (data, width, height) = if is_hdr {
+            let file = File::open(path).map_err(|e| fail(&e))?;
+            let decoder = HdrDecoder::new(BufReader::new(file)).map_err(|e| fail(&e))?;
+            let meta = decoder.metadata();
+            let data: Vec<Color> = decoder
+                .read_image_hdr()
+                .map_err(|e| fail(&e))?
+                .iter()
+                .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
+                .collect();
+            (data, meta.width as usize, meta.height as usize)
+        } else {
+            const CSCALE: f64 = 1.0 / 255.0;
+            let img = image::open(path).map_err(|e| fail(&e))?.to_rgb8();
+            let data: Vec<Color> = img
+                .pixels()
+                .map(|p| {
+                    Color::new(
+                        CSCALE * p[0] as f64,
+                        CSCALE * p[1] as f64,
+                        CSCALE * p[2] as f64,
+                    )
+                })
+                .collect();
+            (data, img.width() as usize, img.height() as usize)
+        };
+        Ok(Self::build(
+            data,
+            width,
+            height,
+            tools::dtr(rotation),
+            intensity,
+        ))
+    }
+
+    // importance by luminance, weighted by sin(theta) for the area each row covers
+    fn build(data: Vec<Color>, width: usize, height: usize, rotation: f64, intensity: f64) -> Self {
+        let mut cols = Vec::with_capacity(height);
+        let mut row_weight = Vec::with_capacity(height);
+        for j in 0..height {
+            let sin_t: f64 = (tools::PI * (j as f64 + 0.5) / height as f64).sin();
+            let func: Vec<f64> = (0..width)
+                .map(|i| color::luminance(data[j * width + i]).max(0.0) * sin_t)
+                .collect();
+            let d = Distribution1D::new(func);
+            row_weight.push(d.integral);
+            cols.push(d);
+        }
+        Self {
+            data,
+            width,
+            height,
+            rotation,
+            intensity,
+            rows: Distribution1D::new(row_weight),
+            cols,
+        }
+    }
+
+    fn texel(&self, i: usize, j: usize) -> Color {
+        self.data[j.min(self.height - 1) * self.width + i.min(self.width - 1)] * self.intensity
+    }
+
+    // direction in map space to (u, v), v = 0 at the top
+    fn to_uv(&self, dir: &Vec3) -> (f64, f64) {
+        let d: Vec3 = dir.unit();
+        let (s, c) = (-self.rotation).sin_cos();
+        let x: f64 = c * d.x() + s * d.z();
+        let z: f64 = -s * d.x() + c * d.z();
+        let phi: f64 = (-z).atan2(x) + tools::PI;
+        let theta: f64 = tools::clamp(d.y(), -1.0, 1.0).acos();
+        (phi / (2.0 * tools::PI), theta / tools::PI)
+    }
+
+    fn from_uv(&self, u: f64, v: f64) -> Vec3 {
+        let phi: f64 = u * 2.0 * tools::PI - tools::PI;
+        let theta: f64 = v * tools::PI;
+        let x: f64 = theta.sin() * phi.cos();
+        let z: f64 = -theta.sin() * phi.sin();
+        let (s, c) = self.rotation.sin_cos();
+        Vec3::new(c * x + s * z, theta.cos(), -s * x + c * z)
+    }
+}
+
+impl Background for EnvMap {
+    fn value(&self, dir: &Vec3) -> Color {
+        let (u, v) = self.to_uv(dir);
+        self.texel(
+            (u * self.width as f64) as usize,
+            (v * self.height as f64) as usize,
+        )
+    }
+
+    fn sample(&self) -> Option<(Vec3, Color, f64)> {
+        let (v, pdf_v, j) = self.rows.sample(tools::randf(0.0, 1.0));
+        let (u, pdf_u, i) = self.cols[j].sample(tools::randf(0.0, 1.0));
+        let sin_t: f64 = (v * tools::PI).sin();
+        if sin_t <= 0.0 {
+            return None;
+        }
+        let pdf: f64 = pdf_u * pdf_v / (2.0 * tools::PI * tools::PI * sin_t);
+        Some((self.from_uv(u, v), self.texel(i, j), pdf))
+    }
+
+    fn pdf(&self, dir: &Vec3) -> f64 {
+        let (u, v) = self.to_uv(dir);
+        let sin_t: f64 = (v * tools::PI).sin();
+        if sin_t <= 0.0 {
+            return 0.0;
+        }
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        self.rows.pdf(j) * self.cols[j].pdf(i) / (2.0 * tools::PI * tools::PI * sin_t)
+    }
+}
+
+// Perez distribution of one Yxy channel relative to the zenith
+fn perez(c: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    (1.0 + c[0] * (c[1] / cos_theta).exp())
+        * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+}
+
+// zenith chromaticity polynomials in turbidity and sun zenith angle
+fn zenith_chroma(m: &[[f64; 4]; 3], t: f64, theta_s: f64) -> f64 {
+    let th = [theta_s * theta_s * theta_s, theta_s * theta_s, theta_s, 1.0];
+    let row = |r: &[f64; 4]| r.iter().zip(th.iter()).map(|(a, b)| a * b).sum::<f64>();
+    t * t * row(&m[0]) + t * row(&m[1]) + row(&m[2])
+}
+
+// analytic daylight of Preetham et al. 1999 with a sun disk; the sky is
+// scaled so a clear midday zenith is roughly 1, the sun is given by the
+// irradiance it adds on a surface facing it
+#[derive(Debug, Clone)]
+pub struct Sky {
+    sun_dir: Vec3,
+    cos_sun: f64, // cosine of the sun's angular radius
+    sun_radiance: Color,
+    intensity: f64,
+    ground: Color,
+    zenith: [f64; 3], // Y, x, y
+    coef: [[f64; 5]; 3],
+    norm: [f64; 3], // perez at the zenith
+}
+
+impl Sky {
+    // sun_dir points towards the sun, the sun is 0.265 degrees wide and adds an irradiance of 3
+    pub fn new(sun_dir: Vec3, turbidity: f64, intensity: f64) -> Self {
+        Self::snew(sun_dir, turbidity, intensity, 0.265, 3.0)
+    }
+    pub fn snew(
+        sun_dir: Vec3,
+        turbidity: f64,
+        intensity: f64,
+        sun_radius: f64,
+        sun_irradiance: f64,
+    ) -> Self {
+        let t: f64 = turbidity;
+        let sun_dir: Vec3 = sun_dir.unit();
+        let theta_s: f64 = tools::clamp(sun_dir.y(), -1.0, 1.0)
+            .acos()
+            .min(tools::PI / 2.0);
+
+        let chi: f64 = (4.0 / 9.0 - t / 120.0) * (tools::PI - 2.0 * theta_s);
+        let big_y: f64 = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let x: f64 = zenith_chroma(
+            &[
+                [0.00166, -0.00375, 0.00209, 0.0],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886],
+            ],
+            t,
+            theta_s,
+        );
+        let y: f64 = zenith_chroma(
+            &[
+                [0.00275, -0.00610, 0.00317, 0.0],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688],
+            ],
+            t,
+            theta_s,
+        );
+        let coef = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        let mut norm = [0.0; 3];
+        for i in 0..3 {
+            norm[i] = perez(&coef[i], 1.0, theta_s);
+        }
+        let mut sky = Self {
+            sun_dir,
+            cos_sun: tools::dtr(sun_radius).cos(),
+            sun_radiance: Color::zero(),
+            intensity,
+            ground: Color::new(0.3, 0.3, 0.3),
+            zenith: [big_y, x, y],
+            coef,
+            norm,
+        };
+        // the sun takes the hue of the sky around it, reddening towards the horizon
+        let around: Color = sky.sky(&sun_dir);
+        let lum: f64 = color::luminance(around);
+        let hue: Color = if lum > 0.0 {
+            around / lum
+        } else {
+            Color::ones()
+        };
+        let solid_angle: f64 = 2.0 * tools::PI * (1.0 - sky.cos_sun);
+        sky.sun_radiance = hue * (sun_irradiance / solid_angle);
+        sky
+    }
+
+    pub fn sun_dir(&self) -> Vec3 {
+        self.sun_dir
+    }
+
+    // sky without the sun disk, dir above the horizon
+    fn sky(&self, dir: &Vec3) -> Color {
+        let d: Vec3 = dir.unit();
+        let cos_theta: f64 = d.y().max(0.01);
+        let gamma: f64 = tools::clamp(d * self.sun_dir, -1.0, 1.0).acos();
+        let mut yxy = [0.0; 3];
+        for i in 0..3 {
+            yxy[i] = self.zenith[i] * perez(&self.coef[i], cos_theta, gamma) / self.norm[i];
+        }
+        let (big_y, x, y) = (yxy[0] / 15.0, yxy[1], yxy[2]);
+        if y <= 0.0 {
+            return Color::zero();
+        }
+        let xyz: Color = Color::new(x / y * big_y, big_y, (1.0 - x - y) / y * big_y);
+        let rgb: Color = spectrum::xyz_to_rgb(xyz);
+        Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0)) * self.intensity
+    }
+}
+
+impl Background for Sky {
+    fn value(&self, dir: &Vec3) -> Color {
+        let d: Vec3 = dir.unit();
+        if d.y() < 0.0 {
+            // a plain ground lit by the horizon
+            let horizon: Vec3 = Vec3::new(d.x(), 0.0, d.z());
+            return Color::elemul(self.ground, self.sky(&horizon));
+        }
+        if d * self.sun_dir >= self.cos_sun {
+            return self.sun_radiance + self.sky(&d);
+        }
+        self.sky(&d)
+    }
+
+    // only the sun is worth sampling, the smooth sky is left to the bsdf
+    fn sample(&self) -> Option<(Vec3, Color, f64)> {
+        let cos_t: f64 = 1.0 - tools::randf(0.0, 1.0) * (1.0 - self.cos_sun);
+        let sin_t: f64 = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+        let phi: f64 = 2.0 * tools::PI * tools::randf(0.0, 1.0);
+        let local: Vec3 = Vec3::new(sin_t * phi.cos(), sin_t * phi.sin(), cos_t);
+        let dir: Vec3 = Onb::new(self.sun_dir).local(local);
+        Some((dir, self.value(&dir), self.pdf(&dir)))
+    }
+
+    fn pdf(&self, dir: &Vec3) -> f64 {
+        if dir.unit() * self.sun_dir >= self.cos_sun {
+            1.0 / (2.0 * tools::PI * (1.0 - self.cos_sun))
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution() {
+        let d = Distribution1D::new(vec![1.0, 0.0, 3.0, 4.0]);
+        // the density integrates to 1 over [0, 1)
+        let total: f64 = (0..4).map(|i| d.pdf(i) / 4.0).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+        // samples stay in their bucket, never in the empty one, and
+        // 1 / pdf averages to the length with any weight, 3 / 4
+        let n = 10000;
+        let mut inv: f64 = 0.0;
+        for k in 0..n {
+            let (x, pdf, i) = d.sample((k as f64 + 0.5) / n as f64);
+            assert_ne!(i, 1);
+            assert!(x >= i as f64 / 4.0 && x <= (i + 1) as f64 / 4.0);
+            assert_eq!(pdf, d.pdf(i));
+            inv += 1.0 / pdf;
+        }
+        assert!((inv / n as f64 - 0.75).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_flat_distribution() {
+        // no weight anywhere falls back to uniform
+        let d = Distribution1D::new(vec![0.0; 5]);
+        for k in 0..10 {
+            let u: f64 = k as f64 / 10.0;
+            let (x, pdf, _) = d.sample(u);
+            assert!((x - u).abs() < 1e-12);
+            assert_eq!(pdf, 1.0);
+        }
+    }
+}
@@ -2,6 +2,7 @@ use crate::ray::Ray;
 use crate::tools;
 use crate::vec3;
 use crate::vec3::Vec3;
+use std::fmt::Debug;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Camera {
@@ -59,7 +60,8 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
+    // the ray through (s, t) leaving at the given time
+    pub fn get_ray_at(&self, s: f64, t: f64, time: f64) -> Ray {
         let rd: Vec3 = vec3::rand_in_unit_disk() * self.lens_radius;
         let offset: Vec3 = self.cu.clone() * rd.x() + self.cv.clone() * rd.y();
         Ray::new(
@@ -69,7 +71,18 @@ impl Camera {
                 + self.vertical.clone() * t
                 - self.origin.clone()
                 - offset.clone(),
-            tools::randf(self.tm0, self.tm1),
+            time,
         )
     }
 }
+
+// sends the primary rays, s and t running over the image from 0 to 1
+pub trait Viewer: Debug + Send + Sync {
+    fn get_ray(&self, s: f64, t: f64) -> Ray;
+}
+
+impl Viewer for Camera {
+    fn get_ray(&self, s: f64, t: f64) -> Ray {
+        self.get_ray_at(s, t, tools::randf(self.tm0, self.tm1))
+    }
+}
@@ -5,6 +5,10 @@ use std::io::prelude::*;
 
 pub type Color = Vec3;
 
+pub fn luminance(color: Color) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
 pub fn out_color(color: Color, samples: i32) -> Color {
     const MAXC: f64 = 256.0;
     let mut r: f64 = color.x();
@@ -0,0 +1,210 @@
+use crate::ray::Ray;
+use crate::shapes::Hitrec;
+use crate::shapes::Hittable;
+use crate::shapes::AABB;
+use crate::tools;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference, // a with b carved out
+}
+
+impl CsgOp {
+    fn inside(&self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOp::Union => in_a || in_b,
+            CsgOp::Intersection => in_a && in_b,
+            CsgOp::Difference => in_a && !in_b,
+        }
+    }
+}
+
+// two closed shapes combined by walking all their crossings along the ray;
+// each surface keeps its own material, so carved faces show b's
+#[derive(Debug, Clone)]
+pub struct Csg {
+    a: Arc<Hittable>,
+    b: Arc<Hittable>,
+    op: CsgOp,
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, a: Arc<Hittable>, b: Arc<Hittable>) -> Self {
+        Self { a, b, op }
+    }
+    pub fn union(a: Arc<Hittable>, b: Arc<Hittable>) -> Self {
+        Self::new(CsgOp::Union, a, b)
+    }
+    pub fn intersection(a: Arc<Hittable>, b: Arc<Hittable>) -> Self {
+        Self::new(CsgOp::Intersection, a, b)
+    }
+    pub fn difference(a: Arc<Hittable>, b: Arc<Hittable>) -> Self {
+        Self::new(CsgOp::Difference, a, b)
+    }
+
+    // the crossings of one operand in (t_min, t_max) and how deep inside it
+    // the ray is at t_min: as many as the exits that come before entries to
+    // match them, and one more when the window seems to end outside but the
+    // next crossing past t_max is an exit
+    fn crossings(shape: &Arc<Hittable>, r: Ray, t_min: f64, t_max: f64) -> (Vec<Hitrec>, i32) {
+        let hits: Vec<Hitrec> = shape.hit_all(r, t_min, t_max);
+        let mut depth: i32 = 0;
+        let mut lack: i32 = 0;
+        for rec in hits.iter() {
+            depth += if rec.front_face { 1 } else { -1 };
+            lack = lack.max(-depth);
+        }
+        if depth + lack == 0 && t_max < tools::INF {
+            if let Some(rec) = shape.hit(r, t_max, tools::INF) {
+                if !rec.front_face {
+                    lack += 1;
+                }
+            }
+        }
+        (hits, lack)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        self.hit_all(r, t_min, t_max).into_iter().next()
+    }
+
+    // the crossings in (t_min, t_max) where the combined inside changes
+    fn hit_all(&self, r: Ray, t_min: f64, t_max: f64) -> Vec<Hitrec> {
+        let (hits_a, mut depth_a) = Self::crossings(&self.a, r, t_min, t_max);
+        let (hits_b, mut depth_b) = Self::crossings(&self.b, r, t_min, t_max);
+        let mut events: Vec<(Hitrec, bool)> = Vec::new();
+        events.extend(hits_a.into_iter().map(|rec| (rec, true)));
+        events.extend(hits_b.into_iter().map(|rec| (rec, false)));
+        events.sort_by(|x, y| x.0.t.partial_cmp(&y.0.t).unwrap());
+
+        let mut hits: Vec<Hitrec> = Vec::new();
+        let mut inside: bool = self.op.inside(depth_a > 0, depth_b > 0);
+        for (mut rec, from_a) in events {
+            let depth = if from_a { &mut depth_a } else { &mut depth_b };
+            if rec.front_face {
+                *depth += 1;
+            } else if *depth > 0 {
+                *depth -= 1;
+            }
+            let now: bool = self.op.inside(depth_a > 0, depth_b > 0);
+            if now == inside {
+                continue;
+            }
+            inside = now;
+            // nf already faces the ray, only which side it came from changes
+            rec.front_face = now;
+            hits.push(rec);
+        }
+        hits
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let a = self.a.bebox(t0, t1)?;
+        match self.op {
+            CsgOp::Difference => Some(a),
+            CsgOp::Union => Some(AABB::merge(a, self.b.bebox(t0, t1)?)),
+            CsgOp::Intersection => {
+                let b = self.b.bebox(t0, t1)?;
+                let mini = Vec3::new(
+                    a.min().x().max(b.min().x()),
+                    a.min().y().max(b.min().y()),
+                    a.min().z().max(b.min().z()),
+                );
+                let maxi = Vec3::new(
+                    a.max().x().min(b.max().x()),
+                    a.max().y().min(b.max().y()),
+                    a.max().z().min(b.max().z()),
+                );
+                // disjoint boxes leave an inverted box no ray enters
+                Some(AABB::new(mini, maxi))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes;
+    use crate::shapes::Sphere;
+    use crate::test_util::gray;
+
+    fn ball(x: f64, rad: f64) -> Arc<Hittable> {
+        Arc::new(Sphere::new(Vec3::new(x, 0.0, 0.0), rad, gray()))
+    }
+    fn along_x() -> Ray {
+        Ray::new(Vec3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0)
+    }
+    fn ts(hits: &[Hitrec]) -> Vec<(f64, bool)> {
+        hits.iter().map(|h| (h.t, h.front_face)).collect()
+    }
+    fn same(got: Vec<(f64, bool)>, want: &[(f64, bool)]) -> bool {
+        got.len() == want.len()
+            && got
+                .iter()
+                .zip(want.iter())
+                .all(|(g, w)| (g.0 - w.0).abs() < 1e-6 && g.1 == w.1)
+    }
+
+    #[test]
+    fn test_ops() {
+        // spheres over [-1, 1] and [0, 2] on the x axis
+        let (a, b) = (ball(0.0, 1.0), ball(1.0, 1.0));
+        let r = along_x();
+        let u = Csg::union(a.clone(), b.clone()).hit_all(r, 0.001, tools::INF);
+        assert!(same(ts(&u), &[(9.0, true), (12.0, false)]));
+        let i = Csg::intersection(a.clone(), b.clone()).hit_all(r, 0.001, tools::INF);
+        assert!(same(ts(&i), &[(10.0, true), (11.0, false)]));
+        let d = Csg::difference(a.clone(), b.clone()).hit_all(r, 0.001, tools::INF);
+        assert!(same(ts(&d), &[(9.0, true), (10.0, false)]));
+        // a ray starting inside the difference only sees its way out
+        let inside = Ray::new(Vec3::new(-0.5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let d = Csg::difference(a, b).hit_all(inside, 0.001, tools::INF);
+        assert!(same(ts(&d), &[(0.5, false)]));
+    }
+
+    #[test]
+    fn test_window() {
+        // starting at t = 9.5 inside a only, with its exit past the window
+        let (a, b) = (ball(0.0, 1.0), ball(1.0, 1.0));
+        let r = along_x();
+        let u = Csg::union(a.clone(), b.clone()).hit_all(r, 9.5, 10.5);
+        assert!(u.is_empty());
+        let i = Csg::intersection(a.clone(), b.clone()).hit_all(r, 9.5, 10.5);
+        assert!(same(ts(&i), &[(10.0, true)]));
+        let d = Csg::difference(a, b).hit_all(r, 9.5, 10.5);
+        assert!(same(ts(&d), &[(10.0, false)]));
+        // inside b's hole, nothing to cross before t = 10.6
+        let d = Csg::difference(ball(0.0, 2.0), ball(0.0, 1.0)).hit_all(r, 9.5, 10.6);
+        assert!(d.is_empty());
+    }
+
+    #[test]
+    fn test_nested() {
+        // a box with a sphere carved out, then cut by a smaller sphere again
+        let cube: Arc<Hittable> = Arc::new(shapes::Boxes::new(
+            Vec3::new(-2.0, -2.0, -2.0),
+            Vec3::new(2.0, 2.0, 2.0),
+            gray(),
+        ));
+        let hollow: Arc<Hittable> = Arc::new(Csg::difference(cube, ball(0.0, 1.5)));
+        let shell = Csg::union(hollow, ball(0.0, 0.5));
+        let hits = shell.hit_all(along_x(), 0.001, tools::INF);
+        let want = [
+            (8.0, true),
+            (8.5, false),
+            (9.5, true),
+            (10.5, false),
+            (11.5, true),
+            (12.0, false),
+        ];
+        assert!(same(ts(&hits), &want));
+        assert!((shell.hit(along_x(), 8.2, tools::INF).unwrap().t - 8.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,373 @@
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::shapes::Hitrec;
+use crate::shapes::Hittable;
+use crate::shapes::AABB;
+use crate::tools;
+use crate::vec3::Onb;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+const MAX_DEPTH: i32 = 10;
+const NEWTON_STEPS: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CurveKind {
+    Ribbon, // a flat strip that always turns to face the ray, for hair and fur
+    Tube,   // a round tube, for anything thick enough to show its shading
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+    a + (b - a) * t
+}
+
+pub fn bezier(cp: &[Vec3; 4], u: f64) -> Vec3 {
+    let (a, b, c) = (
+        lerp(cp[0], cp[1], u),
+        lerp(cp[1], cp[2], u),
+        lerp(cp[2], cp[3], u),
+    );
+    lerp(lerp(a, b, u), lerp(b, c, u), u)
+}
+
+// derivative along u
+pub fn bezier_d(cp: &[Vec3; 4], u: f64) -> Vec3 {
+    let (a, b, c) = (cp[1] - cp[0], cp[2] - cp[1], cp[3] - cp[2]);
+    (lerp(lerp(a, b, u), lerp(b, c, u), u)) * 3.0
+}
+
+// and the second
+pub fn bezier_dd(cp: &[Vec3; 4], u: f64) -> Vec3 {
+    let (a, b) = (cp[2] - cp[1] * 2.0 + cp[0], cp[3] - cp[2] * 2.0 + cp[1]);
+    lerp(a, b, u) * 6.0
+}
+
+// control points of the piece of the curve from u0 to u1
+pub fn sub_bezier(cp: &[Vec3; 4], u0: f64, u1: f64) -> [Vec3; 4] {
+    let blossom = |a: f64, b: f64, c: f64| {
+        let (p, q, r) = (
+            lerp(cp[0], cp[1], a),
+            lerp(cp[1], cp[2], a),
+            lerp(cp[2], cp[3], a),
+        );
+        lerp(lerp(p, q, b), lerp(q, r, b), c)
+    };
+    [
+        blossom(u0, u0, u0),
+        blossom(u0, u0, u1),
+        blossom(u0, u1, u1),
+        blossom(u1, u1, u1),
+    ]
+}
+
+#[derive(Debug, Clone)]
+struct CurveCommon {
+    cp: [Vec3; 4],
+    w0: f64,
+    w1: f64,
+    kind: CurveKind,
+    mat: Arc<Material>,
+}
+
+impl CurveCommon {
+    fn width(&self, u: f64) -> f64 {
+        self.w0 + (self.w1 - self.w0) * u
+    }
+}
+
+// the part from u0 to u1 of a cubic bezier curve whose width goes linearly
+// from w0 to w1; u runs along the whole curve and v across it, and the
+// tangent is passed on for fibre materials like Hair
+#[derive(Debug, Clone)]
+pub struct Curve {
+    common: Arc<CurveCommon>,
+    u0: f64,
+    u1: f64,
+}
+
+impl Curve {
+    pub fn new(cp: [Vec3; 4], w0: f64, w1: f64, kind: CurveKind, mat: Arc<Material>) -> Self {
+        let common = CurveCommon {
+            cp,
+            w0,
+            w1,
+            kind,
+            mat,
+        };
+        Self {
+            common: Arc::new(common),
+            u0: 0.0,
+            u1: 1.0,
+        }
+    }
+
+    // the curve cut into n pieces with their own bounds, for the BVH
+    pub fn split(
+        cp: [Vec3; 4],
+        w0: f64,
+        w1: f64,
+        kind: CurveKind,
+        mat: Arc<Material>,
+        n: usize,
+    ) -> Vec<Arc<Hittable>> {
+        let whole = Self::new(cp, w0, w1, kind, mat);
+        (0..n)
+            .map(|i| {
+                let piece: Arc<Hittable> = Arc::new(Self {
+                    common: whole.common.clone(),
+                    u0: i as f64 / n as f64,
+                    u1: (i + 1) as f64 / n as f64,
+                });
+                piece
+            })
+            .collect()
+    }
+
+    // after pbrt: in a frame where the ray runs down +z from the origin,
+    // split the curve until the pieces are about straight, then check the
+    // distance from the origin to the nearest point of each piece.
+    // gives the depth along the ray, u and v
+    fn recurse(
+        &self,
+        cp: &[Vec3; 4],
+        u0: f64,
+        u1: f64,
+        depth: i32,
+        z_min: f64,
+        z_max: f64,
+    ) -> Option<(f64, f64, f64)> {
+        let hw: f64 = self.common.width(u0).max(self.common.width(u1)) / 2.0;
+        let lo = |f: &dyn Fn(&Vec3) -> f64| cp.iter().map(f).fold(tools::INF, f64::min) - hw;
+        let hi = |f: &dyn Fn(&Vec3) -> f64| cp.iter().map(f).fold(-tools::INF, f64::max) + hw;
+        if lo(&|p| p.x()) > 0.0 || hi(&|p| p.x()) < 0.0 {
+            return None;
+        }
+        if lo(&|p| p.y()) > 0.0 || hi(&|p| p.y()) < 0.0 {
+            return None;
+        }
+        if lo(&|p| p.z()) > z_max || hi(&|p| p.z()) < z_min {
+            return None;
+        }
+
+        if depth > 0 {
+            let um: f64 = (u0 + u1) / 2.0;
+            let first = self.recurse(&sub_bezier(cp, 0.0, 0.5), u0, um, depth - 1, z_min, z_max);
+            let z_max: f64 = first.map_or(z_max, |h| h.0);
+            let second = self.recurse(&sub_bezier(cp, 0.5, 1.0), um, u1, depth - 1, z_min, z_max);
+            return second.or(first);
+        }
+
+        // the origin has to lie between the ends of the piece
+        let edge: f64 = (cp[1].y() - cp[0].y()) * -cp[0].y() + cp[0].x() * (cp[0].x() - cp[1].x());
+        if edge < 0.0 {
+            return None;
+        }
+        let edge: f64 = (cp[2].y() - cp[3].y()) * -cp[3].y() + cp[3].x() * (cp[3].x() - cp[2].x());
+        if edge < 0.0 {
+            return None;
+        }
+
+        let (sx, sy) = (cp[3].x() - cp[0].x(), cp[3].y() - cp[0].y());
+        let denom: f64 = sx * sx + sy * sy;
+        if denom == 0.0 {
+            return None;
+        }
+        let mut w: f64 = tools::clamp(-(cp[0].x() * sx + cp[0].y() * sy) / denom, 0.0, 1.0);
+        // the curve need not move evenly along u, so polish the closest point
+        for _ in 0..NEWTON_STEPS {
+            let (p, d, dd) = (bezier(cp, w), bezier_d(cp, w), bezier_dd(cp, w));
+            let slope: f64 = p.x() * d.x() + p.y() * d.y();
+            let curve: f64 = d.x() * d.x() + d.y() * d.y() + p.x() * dd.x() + p.y() * dd.y();
+            if curve <= 0.0 {
+                break;
+            }
+            w = tools::clamp(w - slope / curve, 0.0, 1.0);
+        }
+        let u: f64 = u0 + (u1 - u0) * w;
+        let width: f64 = self.common.width(u);
+        let pc: Vec3 = bezier(cp, w);
+        let dist2: f64 = pc.x() * pc.x() + pc.y() * pc.y();
+        if dist2 > width * width / 4.0 {
+            return None;
+        }
+        let z: f64 = match self.common.kind {
+            CurveKind::Ribbon => pc.z(),
+            // the near side of the tube, taking it as crossing the ray square on
+            CurveKind::Tube => pc.z() - (width * width / 4.0 - dist2).sqrt(),
+        };
+        if z < z_min || z > z_max {
+            return None;
+        }
+
+        let dw: Vec3 = bezier_d(cp, w);
+        let off: f64 = dist2.sqrt() / width;
+        let v: f64 = if dw.x() * -pc.y() + pc.x() * dw.y() > 0.0 {
+            0.5 + off
+        } else {
+            0.5 - off
+        };
+        Some((z, u, v))
+    }
+
+    fn depth(cp: &[Vec3; 4], width: f64) -> i32 {
+        // how far the control points bend away from a straight line
+        let mut bend: f64 = 0.0;
+        for i in 0..2 {
+            let d: Vec3 = cp[i] - cp[i + 1] * 2.0 + cp[i + 2];
+            bend = bend.max(d.x().abs()).max(d.y().abs()).max(d.z().abs());
+        }
+        let eps: f64 = width / 20.0;
+        if bend <= 0.0 || eps <= 0.0 {
+            return 0;
+        }
+        let r0: f64 = (2.0_f64.sqrt() * 6.0 * bend / (8.0 * eps)).log2() / 2.0;
+        tools::clamp(r0.ceil(), 0.0, MAX_DEPTH as f64) as i32
+    }
+}
+
+impl Hittable for Curve {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let len: f64 = r.diraction().length();
+        let onb = Onb::new(r.diraction());
+        let o: Vec3 = r.origin();
+        let to_ray = |p: Vec3| {
+            let q: Vec3 = p - o;
+            Vec3::new(q * onb.u, q * onb.v, q * onb.w)
+        };
+        let piece: [Vec3; 4] = sub_bezier(&self.common.cp, self.u0, self.u1);
+        let cp: [Vec3; 4] = [
+            to_ray(piece[0]),
+            to_ray(piece[1]),
+            to_ray(piece[2]),
+            to_ray(piece[3]),
+        ];
+        let width: f64 = self.common.width(self.u0).max(self.common.width(self.u1));
+        let depth: i32 = Self::depth(&cp, width);
+        let (z, u, v) = self.recurse(&cp, self.u0, self.u1, depth, t_min * len, t_max * len)?;
+
+        let mut rec = Hitrec::new(self.common.mat.clone());
+        rec.t = z / len;
+        rec.p = r.at(rec.t);
+        rec.u = u;
+        rec.v = v;
+        let tangent: Vec3 = bezier_d(&self.common.cp, u);
+        let axis: Vec3 = if tangent.squared_length() > 0.0 {
+            tangent.unit()
+        } else {
+            onb.u
+        };
+        rec.tangent = axis;
+        let out: Vec3 = match self.common.kind {
+            CurveKind::Ribbon => -r.diraction(),
+            CurveKind::Tube => rec.p - bezier(&self.common.cp, u),
+        };
+        let n: Vec3 = out - axis * (out * axis);
+        let n: Vec3 = if n.squared_length() > 1e-16 {
+            n.unit()
+        } else {
+            -r.diraction().unit()
+        };
+        rec.set_face(r, n);
+        Some(rec)
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let piece: [Vec3; 4] = sub_bezier(&self.common.cp, self.u0, self.u1);
+        let hw: f64 = self.common.width(self.u0).max(self.common.width(self.u1)) / 2.0;
+        let mut mini = Vec3::ones() * tools::INF;
+        let mut maxi = Vec3::ones() * -tools::INF;
+        for p in piece.iter() {
+            mini = Vec3::new(
+                mini.x().min(p.x()),
+                mini.y().min(p.y()),
+                mini.z().min(p.z()),
+            );
+            maxi = Vec3::new(
+                maxi.x().max(p.x()),
+                maxi.y().max(p.y()),
+                maxi.z().max(p.z()),
+            );
+        }
+        Some(AABB::new(
+            mini - Vec3::ones() * hw,
+            maxi + Vec3::ones() * hw,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gray;
+
+    // a straight curve along x from -2 to 2, narrowing from 0.4 to 0.2
+    fn straight() -> [Vec3; 4] {
+        [
+            Vec3::new(-2.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+        ]
+    }
+    fn down(x: f64, z: f64) -> Ray {
+        Ray::new(Vec3::new(x, 5.0, z), Vec3::new(0.0, -2.0, 0.0), 0.0)
+    }
+
+    #[test]
+    fn test_ribbon() {
+        let c = Curve::new(straight(), 0.4, 0.2, CurveKind::Ribbon, gray());
+        let rec = c.hit(down(0.0, 0.05), 0.001, tools::INF).unwrap();
+        assert!((rec.t - 2.5).abs() < 1e-9 && rec.front_face);
+        assert!((rec.u - 0.5).abs() < 1e-9);
+        assert!(((rec.v - 0.5).abs() - 0.05 / 0.3).abs() < 1e-9);
+        assert!((rec.nf - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+        assert!((rec.tangent - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+        // wide enough near the thick end but not near the thin one
+        assert!(c.hit(down(-1.9, 0.18), 0.001, tools::INF).is_some());
+        assert!(c.hit(down(1.9, 0.18), 0.001, tools::INF).is_none());
+        assert!(c.hit(down(2.2, 0.0), 0.001, tools::INF).is_none());
+    }
+
+    #[test]
+    fn test_tube() {
+        let c = Curve::new(straight(), 0.4, 0.4, CurveKind::Tube, gray());
+        let rec = c.hit(down(0.5, 0.1), 0.001, tools::INF).unwrap();
+        let y: f64 = (0.04 - 0.01_f64).sqrt();
+        assert!((rec.p.y() - y).abs() < 1e-9);
+        assert!((rec.nf - Vec3::new(0.0, y, 0.1) / 0.2).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_split() {
+        // a bent curve, every hit of the whole must be found by one of its pieces
+        let cp = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.5, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 2.0),
+            Vec3::new(3.0, 0.0, 0.0),
+        ];
+        let whole = Curve::new(cp, 0.3, 0.05, CurveKind::Ribbon, gray());
+        let pieces = Curve::split(cp, 0.3, 0.05, CurveKind::Ribbon, gray(), 4);
+        // the pieces are straightened at other depths, which can only move
+        // the very edges of the strip
+        let mut differ: i32 = 0;
+        for _ in 0..500 {
+            let r = down(tools::randf(-0.5, 3.5), tools::randf(-0.5, 2.0));
+            let a = whole.hit(r, 0.001, tools::INF).is_some();
+            let mut b = false;
+            for p in pieces.iter() {
+                if let Some(h) = p.hit(r, 0.001, tools::INF) {
+                    assert!((h.t - 2.5).abs() < 1e-9);
+                    let bb = p.bebox(0.0, 1.0).unwrap();
+                    assert!(h.p.x() >= bb.min().x() && h.p.x() <= bb.max().x());
+                    assert!(h.p.z() >= bb.min().z() && h.p.z() <= bb.max().z());
+                    b = true;
+                }
+            }
+            if a != b {
+                differ += 1;
+            }
+        }
+        assert!(differ <= 5);
+    }
+}
@@ -0,0 +1,422 @@
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::shapes::Hitrec;
+use crate::shapes::Hittable;
+use crate::shapes::AABB;
+use crate::tools;
+use crate::vec3::Vec3;
+use std::path::Path;
+use std::sync::Arc;
+
+// lowest and highest sample under each block of cells, every level
+// covering twice as many cells a side as the one below
+#[derive(Debug, Clone)]
+struct MinMax {
+    w: usize,
+    h: usize,
+    lo: Vec<f64>,
+    hi: Vec<f64>,
+}
+
+impl MinMax {
+    fn at(&self, x: usize, z: usize) -> (f64, f64) {
+        (self.lo[z * self.w + x], self.hi[z * self.w + x])
+    }
+
+    // the level above, blocks of up to 2x2 of these
+    fn up(&self) -> Self {
+        let (w, h) = ((self.w + 1) / 2, (self.h + 1) / 2);
+        let mut lo: Vec<f64> = vec![tools::INF; w * h];
+        let mut hi: Vec<f64> = vec![-tools::INF; w * h];
+        for z in 0..self.h {
+            for x in 0..self.w {
+                let (a, b) = self.at(x, z);
+                let k = (z / 2) * w + x / 2;
+                lo[k] = lo[k].min(a);
+                hi[k] = hi[k].max(b);
+            }
+        }
+        Self { w, h, lo, hi }
+    }
+}
+
+// where the ray is inside the box, within t0 and t1
+fn slab(r: &Ray, lo: Vec3, hi: Vec3, t0: f64, t1: f64) -> Option<(f64, f64)> {
+    let (mut t0, mut t1) = (t0, t1);
+    let (o, d) = (r.origin(), r.diraction());
+    for &(o, d, lo, hi) in [
+        (o.x(), d.x(), lo.x(), hi.x()),
+        (o.y(), d.y(), lo.y(), hi.y()),
+        (o.z(), d.z(), lo.z(), hi.z()),
+    ]
+    .iter()
+    {
+        let inv: f64 = 1.0 / d;
+        let (mut a, mut b) = ((lo - o) * inv, (hi - o) * inv);
+        if inv < 0.0 {
+            std::mem::swap(&mut a, &mut b);
+        }
+        t0 = t0.max(a);
+        t1 = t1.min(b);
+        if t1 < t0 {
+            return None;
+        }
+    }
+    Some((t0, t1))
+}
+
+// two sided ray triangle test, t and the barycentrics of b and c
+fn triangle(r: &Ray, a: Vec3, b: Vec3, c: Vec3) -> Option<(f64, f64, f64)> {
+    let (e1, e2) = (b - a, c - a);
+    let pv: Vec3 = Vec3::cross(r.diraction(), e2);
+    let det: f64 = e1 * pv;
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv: f64 = 1.0 / det;
+    let s: Vec3 = r.origin() - a;
+    let u: f64 = (s * pv) * inv;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+    let qv: Vec3 = Vec3::cross(s, e1);
+    let v: f64 = (r.diraction() * qv) * inv;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    Some(((e2 * qv) * inv, u, v))
+}
+
+// terrain over an nx by nz grid of height samples, lying on the xz plane
+// from corner; every cell is split into two triangles with normals blended
+// from the neighbouring samples. uv follows the image it was loaded from
+#[derive(Debug, Clone)]
+pub struct Heightfield {
+    heights: Vec<f64>, // world y of each sample, rows along x
+    normals: Vec<Vec3>,
+    nx: usize,
+    nz: usize,
+    corner: Vec3,
+    dx: f64,
+    dz: f64,
+    levels: Vec<MinMax>,
+    mat: Arc<Material>,
+}
+
+impl Heightfield {
+    // samples in 0..1 scaled to size.y, spread over size.x by size.z
+    pub fn new(
+        samples: Vec<f64>,
+        nx: usize,
+        nz: usize,
+        corner: Vec3,
+        size: Vec3,
+        mat: Arc<Material>,
+    ) -> Self {
+        assert!(
+            nx >= 2 && nz >= 2,
+            "a heightfield needs at least 2x2 samples"
+        );
+        assert_eq!(samples.len(), nx * nz);
+        let heights: Vec<f64> = samples.iter().map(|s| corner.y() + s * size.y()).collect();
+        let dx: f64 = size.x() / (nx - 1) as f64;
+        let dz: f64 = size.z() / (nz - 1) as f64;
+
+        let h = |x: usize, z: usize| heights[z * nx + x];
+        let mut normals: Vec<Vec3> = Vec::with_capacity(nx * nz);
+        for z in 0..nz {
+            for x in 0..nx {
+                let (x0, x1) = (x.saturating_sub(1), (x + 1).min(nx - 1));
+                let (z0, z1) = (z.saturating_sub(1), (z + 1).min(nz - 1));
+                let sx: f64 = (h(x1, z) - h(x0, z)) / ((x1 - x0) as f64 * dx);
+                let sz: f64 = (h(x, z1) - h(x, z0)) / ((z1 - z0) as f64 * dz);
+                normals.push(Vec3::new(-sx, 1.0, -sz).unit());
+            }
+        }
+
+        let (w, d) = (nx - 1, nz - 1);
+        let mut cells = MinMax {
+            w,
+            h: d,
+            lo: Vec::with_capacity(w * d),
+            hi: Vec::with_capacity(w * d),
+        };
+        for z in 0..d {
+            for x in 0..w {
+                let c = [h(x, z), h(x + 1, z), h(x, z + 1), h(x + 1, z + 1)];
+                cells.lo.push(c.iter().cloned().fold(tools::INF, f64::min));
+                cells.hi.push(c.iter().cloned().fold(-tools::INF, f64::max));
+            }
+        }
+        let mut levels: Vec<MinMax> = vec![cells];
+        while levels.last().unwrap().w > 1 || levels.last().unwrap().h > 1 {
+            let next = levels.last().unwrap().up();
+            levels.push(next);
+        }
+
+        Self {
+            heights,
+            normals,
+            nx,
+            nz,
+            corner,
+            dx,
+            dz,
+            levels,
+            mat,
+        }
+    }
+
+    // heights from f over (u, v) in 0..1, e.g. a noise function
+    pub fn fnew<F: Fn(f64, f64) -> f64>(
+        f: F,
+        nx: usize,
+        nz: usize,
+        corner: Vec3,
+        size: Vec3,
+        mat: Arc<Material>,
+    ) -> Self {
+        let mut samples: Vec<f64> = Vec::with_capacity(nx * nz);
+        for z in 0..nz {
+            for x in 0..nx {
+                let (u, v) = Self::uv(x as f64, z as f64, nx, nz);
+                samples.push(f(u, v));
+            }
+        }
+        Self::new(samples, nx, nz, corner, size, mat)
+    }
+
+    // one sample per pixel of a grayscale image, white being size.y high;
+    // the top row of the image lies at corner.z. fails with the file's name
+    // when it is missing, cannot be decoded or is smaller than 2x2
+    pub fn open(path: &Path, corner: Vec3, size: Vec3, mat: Arc<Material>) -> Result<Self, String> {
+        let img = image::open(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?
+            .to_luma8();
+        let (nx, nz) = (img.width() as usize, img.height() as usize);
+        if nx < 2 || nz < 2 {
+            return Err(format!(
+                "cannot read {}: {}x{} is too small for a heightfield",
+                path.display(),
+                nx,
+                nz
+            ));
+        }
+        let samples: Vec<f64> = img.pixels().map(|p| p[0] as f64 / 255.0).collect();
+        Ok(Self::new(samples, nx, nz, corner, size, mat))
+    }
+
+    // image style uv, v = 1 on the first row
+    fn uv(x: f64, z: f64, nx: usize, nz: usize) -> (f64, f64) {
+        (x / (nx - 1) as f64, 1.0 - z / (nz - 1) as f64)
+    }
+
+    fn vertex(&self, x: usize, z: usize) -> Vec3 {
+        Vec3::new(
+            self.corner.x() + x as f64 * self.dx,
+            self.heights[z * self.nx + x],
+            self.corner.z() + z as f64 * self.dz,
+        )
+    }
+
+    // the cells a block of a level spans along x and z
+    fn cells(&self, level: usize, x: usize, z: usize) -> (usize, usize, usize, usize) {
+        let s: usize = 1 << level;
+        let (w, d) = (self.nx - 1, self.nz - 1);
+        (x * s, ((x + 1) * s).min(w), z * s, ((z + 1) * s).min(d))
+    }
+
+    fn hit_cell(&self, r: &Ray, x: usize, z: usize, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let corners = [(x, z), (x + 1, z), (x + 1, z + 1), (x, z + 1)];
+        let mut best: Option<(f64, [(usize, usize); 3], f64, f64)> = None;
+        for tri in [[0, 1, 2], [0, 2, 3]].iter() {
+            let v = [corners[tri[0]], corners[tri[1]], corners[tri[2]]];
+            let p = |i: usize| self.vertex(v[i].0, v[i].1);
+            if let Some((t, b1, b2)) = triangle(r, p(0), p(1), p(2)) {
+                let closest = best.map_or(t_max, |b| b.0);
+                if t > t_min && t < closest {
+                    best = Some((t, v, b1, b2));
+                }
+            }
+        }
+        let (t, v, b1, b2) = best?;
+        let w = [1.0 - b1 - b2, b1, b2];
+
+        let mut rec = Hitrec::new(self.mat.clone());
+        rec.t = t;
+        rec.p = r.at(t);
+        let (mut shade, mut u, mut vv) = (Vec3::zero(), 0.0, 0.0);
+        for i in 0..3 {
+            let (px, pz) = v[i];
+            shade += self.normals[pz * self.nx + px] * w[i];
+            let (a, b) = Self::uv(px as f64, pz as f64, self.nx, self.nz);
+            u += a * w[i];
+            vv += b * w[i];
+        }
+        rec.u = u;
+        rec.v = vv;
+        // the side comes from the flat triangle, the shading from the blend
+        let (a, b, c) = (
+            self.vertex(v[0].0, v[0].1),
+            self.vertex(v[1].0, v[1].1),
+            self.vertex(v[2].0, v[2].1),
+        );
+        let mut geo: Vec3 = Vec3::cross(b - a, c - a);
+        if geo.y() < 0.0 {
+            geo = -geo;
+        }
+        rec.set_face(*r, geo.unit());
+        let shade: Vec3 = shade.unit();
+        rec.nf = if rec.front_face { shade } else { -shade };
+        Some(rec)
+    }
+}
+
+impl Hittable for Heightfield {
+    // walks the min-max levels from the top, only opening blocks whose
+    // height range the ray passes through before the closest hit so far
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let mut closest: f64 = t_max;
+        let mut found: Option<Hitrec> = None;
+        let flip_x: bool = r.diraction().x() < 0.0;
+        let flip_z: bool = r.diraction().z() < 0.0;
+        let mut stack: Vec<(usize, usize, usize)> = vec![(self.levels.len() - 1, 0, 0)];
+        while let Some((level, x, z)) = stack.pop() {
+            let (lo, hi) = self.levels[level].at(x, z);
+            let (x0, x1, z0, z1) = self.cells(level, x, z);
+            let bmin = Vec3::new(
+                self.corner.x() + x0 as f64 * self.dx,
+                lo,
+                self.corner.z() + z0 as f64 * self.dz,
+            );
+            let bmax = Vec3::new(
+                self.corner.x() + x1 as f64 * self.dx,
+                hi,
+                self.corner.z() + z1 as f64 * self.dz,
+            );
+            if slab(&r, bmin, bmax, t_min, closest).is_none() {
+                continue;
+            }
+            if level == 0 {
+                if let Some(rec) = self.hit_cell(&r, x, z, t_min, closest) {
+                    closest = rec.t;
+                    found = Some(rec);
+                }
+                continue;
+            }
+            // push the far children first so the near ones are tried first
+            let below = &self.levels[level - 1];
+            for &(i, j) in [(1, 1), (0, 1), (1, 0), (0, 0)].iter() {
+                let cx = 2 * x + if flip_x { 1 - i } else { i };
+                let cz = 2 * z + if flip_z { 1 - j } else { j };
+                if cx < below.w && cz < below.h {
+                    stack.push((level - 1, cx, cz));
+                }
+            }
+        }
+        found
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let lit = 0.0001;
+        let (lo, hi) = self.levels.last().unwrap().at(0, 0);
+        let far: Vec3 = self.vertex(self.nx - 1, self.nz - 1);
+        Some(AABB::new(
+            Vec3::new(self.corner.x(), lo - lit, self.corner.z()),
+            Vec3::new(far.x(), hi + lit, far.z()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gray;
+
+    fn down(x: f64, z: f64) -> Ray {
+        Ray::new(Vec3::new(x, 10.0, z), Vec3::new(0.0, -1.0, 0.0), 0.0)
+    }
+
+    #[test]
+    fn test_slope() {
+        // a ramp rising along x from 0 to 2 over 4 units
+        let ramp = Heightfield::fnew(
+            |u, _| u,
+            9,
+            5,
+            Vec3::zero(),
+            Vec3::new(4.0, 2.0, 4.0),
+            gray(),
+        );
+        let rec = ramp.hit(down(1.3, 2.7), 0.001, tools::INF).unwrap();
+        assert!((rec.t - (10.0 - 0.65)).abs() < 1e-9 && rec.front_face);
+        let want: Vec3 = Vec3::new(-0.5, 1.0, 0.0).unit();
+        assert!((rec.nf - want).length() < 1e-9);
+        assert!((rec.u - 1.3 / 4.0).abs() < 1e-9 && (rec.v - (1.0 - 2.7 / 4.0)).abs() < 1e-9);
+        assert!(ramp.hit(down(4.5, 2.0), 0.001, tools::INF).is_none());
+        // from underneath the back side is hit
+        let up = Ray::new(Vec3::new(1.0, -5.0, 1.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let rec = ramp.hit(up, 0.001, tools::INF).unwrap();
+        assert!(!rec.front_face && rec.nf.y() < 0.0);
+    }
+
+    #[test]
+    fn test_against_brute_force() {
+        // bumpy and not a power of two a side, so the levels have ragged edges
+        let field = Heightfield::fnew(
+            |u, v| 0.5 + 0.5 * (13.0 * u).sin() * (7.0 * v).cos(),
+            23,
+            14,
+            Vec3::new(-3.0, 0.0, -2.0),
+            Vec3::new(6.0, 1.5, 4.0),
+            gray(),
+        );
+        for _ in 0..2000 {
+            let org = Vec3::new(
+                tools::randf(-4.0, 4.0),
+                tools::randf(0.5, 4.0),
+                tools::randf(-3.0, 3.0),
+            );
+            let dir = Vec3::new(
+                tools::randf(-1.0, 1.0),
+                tools::randf(-1.0, 0.2),
+                tools::randf(-1.0, 1.0),
+            );
+            let r = Ray::new(org, dir, 0.0);
+            let mut want: f64 = tools::INF;
+            for z in 0..field.nz - 1 {
+                for x in 0..field.nx - 1 {
+                    if let Some(rec) = field.hit_cell(&r, x, z, 0.001, want) {
+                        want = rec.t;
+                    }
+                }
+            }
+            match field.hit(r, 0.001, tools::INF) {
+                Some(rec) => assert!((rec.t - want).abs() < 1e-9),
+                None => assert!(want == tools::INF),
+            }
+        }
+    }
+
+    #[test]
+    fn test_open() {
+        let dir = std::env::temp_dir();
+        let path = dir.join("heightfield_test_open.png");
+        // a 3x2 image, white in the middle column
+        image::GrayImage::from_raw(3, 2, vec![0, 255, 0, 0, 255, 0])
+            .unwrap()
+            .save(&path)
+            .unwrap();
+        let field = Heightfield::open(&path, Vec3::zero(), Vec3::new(2.0, 1.0, 1.0), gray());
+        std::fs::remove_file(&path).unwrap();
+        let rec = field
+            .unwrap()
+            .hit(down(1.0, 0.5), 0.001, tools::INF)
+            .unwrap();
+        assert!((rec.t - 9.0).abs() < 1e-9);
+
+        let missing = dir.join("heightfield_test_missing.png");
+        let e = Heightfield::open(&missing, Vec3::zero(), Vec3::ones(), gray()).unwrap_err();
+        assert!(e.contains("heightfield_test_missing.png"));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::tools;
+use crate::vec3::Onb;
+use crate::vec3::Vec3;
+use std::fs;
+use std::path::Path;
+
+// IESNA LM-63 photometric profile of a type C luminaire: candela over
+// vertical angles from the nadir and horizontal angles around it
+#[derive(Debug, Clone)]
+pub struct IesProfile {
+    pub vertical: Vec<f64>,     // degrees, 0 points along the luminaire axis
+    pub horizontal: Vec<f64>,   // degrees
+    pub candela: Vec<Vec<f64>>, // one row of vertical samples per horizontal angle
+    max: f64,
+}
+
+impl IesProfile {
+    pub fn new(path: &Path) -> Self {
+        let text = fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("cannot open {}: {}", path.display(), e));
+        Self::parse(&text).unwrap_or_else(|| panic!("malformed IES profile {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        // keywords come first, the numbers after TILT= may wrap lines freely
+        let mut lines = text.lines();
+        let tilt = loop {
+            let line = lines.next()?.trim();
+            if let Some(rest) = line.strip_prefix("TILT=") {
+                break rest.trim().to_string();
+            }
+        };
+        let rest: Vec<&str> = lines.collect();
+        let mut nums = rest
+            .iter()
+            .flat_map(|l| l.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<f64>());
+        let mut next = || -> Option<f64> { nums.next()?.ok() };
+
+        if tilt == "INCLUDE" {
+            // lamp to luminaire geometry, then pairs of tilt angles and factors
+            next()?;
+            let n = next()? as usize;
+            for _ in 0..(2 * n) {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let n_vertical = next()? as usize;
+        let n_horizontal = next()? as usize;
+        let photometric_type = next()?;
+        for _ in 0..4 {
+            next()?; // units, width, length, height
+        }
+        let ballast = next()?;
+        let ballast_lamp = next()?;
+        let _watts = next()?;
+        if photometric_type != 1.0 || n_vertical < 1 || n_horizontal < 1 {
+            return None;
+        }
+
+        let scale: f64 = multiplier * ballast * ballast_lamp;
+        let mut vertical = Vec::with_capacity(n_vertical);
+        for _ in 0..n_vertical {
+            vertical.push(next()?);
+        }
+        let mut horizontal = Vec::with_capacity(n_horizontal);
+        for _ in 0..n_horizontal {
+            horizontal.push(next()?);
+        }
+        let mut candela = Vec::with_capacity(n_horizontal);
+        let mut max: f64 = 0.0;
+        for _ in 0..n_horizontal {
+            let mut row = Vec::with_capacity(n_vertical);
+            for _ in 0..n_vertical {
+                let c: f64 = next()? * scale;
+                max = max.max(c);
+                row.push(c);
+            }
+            candela.push(row);
+        }
+        Some(Self {
+            vertical,
+            horizontal,
+            candela,
+            max,
+        })
+    }
+
+    // fold a horizontal angle into the range the table covers
+    fn fold(&self, h: f64) -> f64 {
+        let last: f64 = *self.horizontal.last().unwrap();
+        let h: f64 = h.rem_euclid(360.0);
+        if last <= 0.0 {
+            0.0 // axially symmetric
+        } else if last <= 90.0 {
+            // quadrant symmetric
+            let h = h % 180.0;
+            if h > 90.0 {
+                180.0 - h
+            } else {
+                h
+            }
+        } else if last <= 180.0 {
+            // bilateral about the 0-180 plane
+            if h > 180.0 {
+                360.0 - h
+            } else {
+                h
+            }
+        } else {
+            h
+        }
+    }
+
+    // index and weight of the sample pair around x in a sorted table
+    fn bracket(table: &[f64], x: f64) -> (usize, usize, f64) {
+        let n = table.len();
+        if n == 1 || x <= table[0] {
+            return (0, 0, 0.0);
+        }
+        if x >= table[n - 1] {
+            return (n - 1, n - 1, 0.0);
+        }
+        let mut i = 0;
+        while table[i + 1] < x {
+            i += 1;
+        }
+        let t: f64 = (x - table[i]) / (table[i + 1] - table[i]);
+        (i, i + 1, t)
+    }
+
+    // candela in the direction given by the two angles in degrees, bilinear in the table
+    pub fn candela(&self, vertical: f64, horizontal: f64) -> f64 {
+        let (v0, v1, tv) = Self::bracket(&self.vertical, vertical);
+        let (h0, h1, th) = Self::bracket(&self.horizontal, self.fold(horizontal));
+        let row = |h: usize| self.candela[h][v0] * (1.0 - tv) + self.candela[h][v1] * tv;
+        // outside the measured vertical angles the luminaire is dark
+        if vertical < self.vertical[0] - 1e-9 || vertical > *self.vertical.last().unwrap() + 1e-9 {
+            return 0.0;
+        }
+        row(h0) * (1.0 - th) + row(h1) * th
+    }
+
+    pub fn max_candela(&self) -> f64 {
+        self.max
+    }
+
+    // relative intensity in [0, 1] towards a unit direction, the frame's w
+    // is the luminaire axis and its u the 0 degree horizontal plane
+    pub fn value(&self, frame: &Onb, dir: &Vec3) -> f64 {
+        if self.max <= 0.0 {
+            return 0.0;
+        }
+        let cos_v: f64 = tools::clamp(*dir * frame.w, -1.0, 1.0);
+        let vertical: f64 = cos_v.acos() * 180.0 / tools::PI;
+        let horizontal: f64 = (*dir * frame.v).atan2(*dir * frame.u) * 180.0 / tools::PI;
+        self.candela(vertical, horizontal) / self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = "IESNA:LM-63-2002
+[TEST] quadrant symmetric fixture
+[MANUFAC] none
+TILT=NONE
+1 1000 2.0 5 3 1 2 0.0 0.0 0.0
+1.0 1.0 40
+0 22.5 45 67.5 90
+0 45 90
+1000 900 600 200 0
+800 700 500 150 0
+600 500 300 100
+0
+";
+
+    #[test]
+    fn test_parse() {
+        let p = IesProfile::parse(PROFILE).unwrap();
+        assert_eq!(p.vertical, vec![0.0, 22.5, 45.0, 67.5, 90.0]);
+        assert_eq!(p.horizontal, vec![0.0, 45.0, 90.0]);
+        assert_eq!(p.candela[1], vec![1600.0, 1400.0, 1000.0, 300.0, 0.0]);
+        assert_eq!(p.max_candela(), 2000.0);
+    }
+
+    #[test]
+    fn test_table_angles() {
+        let p = IesProfile::parse(PROFILE).unwrap();
+        let raw = [
+            [1000.0, 900.0, 600.0, 200.0, 0.0],
+            [800.0, 700.0, 500.0, 150.0, 0.0],
+            [600.0, 500.0, 300.0, 100.0, 0.0],
+        ];
+        for (h, row) in raw.iter().enumerate() {
+            for (v, c) in row.iter().enumerate() {
+                let got: f64 = p.candela(p.vertical[v], p.horizontal[h]);
+                assert!((got - 2.0 * c).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let p = IesProfile::parse(PROFILE).unwrap();
+        // halfway between vertical samples and between horizontal planes
+        assert!((p.candela(11.25, 0.0) - 1900.0).abs() < 1e-9);
+        assert!((p.candela(0.0, 22.5) - 1800.0).abs() < 1e-9);
+        assert!((p.candela(11.25, 22.5) - 1700.0).abs() < 1e-9);
+        assert_eq!(p.candela(120.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_vertical_range() {
+        // an uplight measured from 90 to 180 degrees only
+        let p = IesProfile::parse(
+            "IESNA:LM-63-2002
+TILT=NONE
+1 1000 1.0 3 1 1 2 0.0 0.0 0.0
+1.0 1.0 40
+90 135 180
+0
+100 200 300
+",
+        )
+        .unwrap();
+        assert_eq!(p.candela(45.0, 0.0), 0.0);
+        assert_eq!(p.candela(89.0, 0.0), 0.0);
+        assert!((p.candela(90.0, 0.0) - 100.0).abs() < 1e-9);
+        assert!((p.candela(157.5, 0.0) - 250.0).abs() < 1e-9);
+        assert!((p.candela(180.0, 0.0) - 300.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_symmetry() {
+        let p = IesProfile::parse(PROFILE).unwrap();
+        for v in [0.0, 30.0, 60.0].iter() {
+            let c: f64 = p.candela(*v, 30.0);
+            assert!((p.candela(*v, 150.0) - c).abs() < 1e-9);
+            assert!((p.candela(*v, 210.0) - c).abs() < 1e-9);
+            assert!((p.candela(*v, 330.0) - c).abs() < 1e-9);
+            assert!((p.candela(*v, -30.0) - c).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_value() {
+        let p = IesProfile::parse(PROFILE).unwrap();
+        let frame = Onb::new(Vec3::new(0.0, -1.0, 0.0));
+        assert!((p.value(&frame, &Vec3::new(0.0, -1.0, 0.0)) - 1.0).abs() < 1e-9);
+        let side: Vec3 = (frame.w + frame.u).unit();
+        assert!((p.value(&frame, &side) - 0.6).abs() < 1e-9);
+        assert_eq!(p.value(&frame, &Vec3::new(0.0, 1.0, 0.0)), 0.0);
+    }
+}
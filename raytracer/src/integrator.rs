@@ -0,0 +1,452 @@
+use crate::background::Background;
+use crate::color::Color;
+use crate::light::Light;
+use crate::ray::Ray;
+use crate::shapes::BvhNode;
+use crate::shapes::Hitrec;
+use crate::shapes::Hittable;
+use crate::spectrum::Spectrum;
+use crate::spectrum::Wavelengths;
+use crate::tools;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// everything a path carries from one bounce to the next
+#[derive(Debug, Clone, Copy)]
+pub struct PathState {
+    pub ray: Ray,
+    pub throughput: Color,
+    pub radiance: Color,
+    pub depth: i32,
+    pub pdf: f64, // of the last scattered direction when lights and background were sampled there too
+}
+
+impl PathState {
+    pub fn new(ray: Ray) -> Self {
+        Self {
+            ray,
+            throughput: Color::ones(),
+            radiance: Color::zero(),
+            depth: 0,
+            pdf: 0.0,
+        }
+    }
+    // add radiance arriving along the current path
+    pub fn add(&mut self, l: Color) {
+        self.radiance += Color::elemul(self.throughput, l);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Integrator {
+    pub max_depth: i32,
+    pub rr_depth: i32, // paths shorter than this are never terminated by russian roulette
+    pub spectral: bool,
+    pub lights: Vec<Arc<Light>>,
+}
+
+fn power_heuristic(a: f64, b: f64) -> f64 {
+    a * a / (a * a + b * b)
+}
+
+impl Integrator {
+    pub fn new(max_depth: i32, rr_depth: i32) -> Self {
+        Self {
+            max_depth,
+            rr_depth,
+            spectral: false,
+            lights: Vec::new(),
+        }
+    }
+
+    fn visible(&self, p: Vec3, dir: Vec3, dist: f64, time: f64, world: &BvhNode) -> bool {
+        world
+            .hit(Ray::new(p, dir, time), 0.001, dist * (1.0 - 1e-6))
+            .is_none()
+    }
+
+    // light arriving at rec straight from the lights and the sampled part of
+    // the background, weighted against hitting the background by scattering
+    fn direct(&self, r_in: &Ray, rec: &Hitrec, background: &Background, world: &BvhNode) -> Color {
+        let mut l: Color = Color::zero();
+        let evaluable: bool = rec.mat.evaluable(rec);
+        for light in self.lights.iter() {
+            if let Some((wi, dist, li)) = light.sample_li(&rec.p) {
+                let w: f64 = match Self::light_weight(&**light, r_in, rec, &wi) {
+                    Some(w) => w,
+                    None => continue,
+                };
+                let f: Color = rec.mat.eval(r_in, rec, &wi);
+                if f.squared_length() > 0.0 && self.visible(rec.p, wi, dist, r_in.time(), world) {
+                    l += Color::elemul(f, li) * w;
+                }
+            }
+        }
+        if !evaluable {
+            return l;
+        }
+        if let Some((wi, le, pdf)) = background.sample() {
+            let f: Color = rec.mat.eval(r_in, rec, &wi);
+            if pdf > 0.0
+                && f.squared_length() > 0.0
+                && self.visible(rec.p, wi, tools::INF, r_in.time(), world)
+            {
+                let w: f64 = power_heuristic(pdf, rec.mat.pdf(r_in, rec, &wi));
+                l += Color::elemul(f, le) * (w / pdf);
+            }
+        }
+        l
+    }
+
+    // weight of a light sample towards wi: lights a scattered ray can also hit
+    // are shared between the two by MIS, none when the material cannot weigh
+    // its share and leaves all of it to scattering
+    fn light_weight(light: &Light, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Option<f64> {
+        let light_pdf: f64 = light.pdf_li(&rec.p, wi);
+        if light_pdf <= 0.0 {
+            return Some(1.0);
+        }
+        if !rec.mat.evaluable(rec) {
+            return None;
+        }
+        Some(power_heuristic(light_pdf, rec.mat.pdf(r_in, rec, wi)))
+    }
+
+    // pdf of the scattered direction for the background weight, 0 when the
+    // background was not sampled at this vertex
+    fn scatter_pdf(r_in: &Ray, rec: &Hitrec, scat: &Ray) -> f64 {
+        if rec.mat.evaluable(rec) {
+            rec.mat.pdf(r_in, rec, &scat.diraction().unit())
+        } else {
+            0.0
+        }
+    }
+
+    fn background_weight(pdf: f64, background: &Background, dir: &Vec3) -> f64 {
+        if pdf > 0.0 {
+            power_heuristic(pdf, background.pdf(dir))
+        } else {
+            1.0
+        }
+    }
+
+    // weight of emission met by a scattered ray against sampling the area
+    // lights; only the light the ray hit could have sampled it, an emitter
+    // that is no registered light keeps its full weight
+    fn emission_weight(&self, pdf: f64, r: &Ray, rec: &Hitrec) -> f64 {
+        if pdf <= 0.0 {
+            return 1.0;
+        }
+        let light_pdf: f64 = self
+            .lights
+            .iter()
+            .filter(|l| l.owns(r, rec.t))
+            .map(|l| l.pdf_li(&r.origin(), &r.diraction()))
+            .sum();
+        power_heuristic(pdf, light_pdf)
+    }
+
+    pub fn li(&self, r: Ray, background: &Background, world: &BvhNode) -> Color {
+        if self.spectral {
+            return self.li_spectral(r, background, world);
+        }
+        let mut st = PathState::new(r);
+        let mut t_min: f64 = 0.001;
+        while st.depth < self.max_depth {
+            let rec = match world.hit(st.ray, t_min, tools::INF) {
+                Some(rec) => rec,
+                None => {
+                    let dir: Vec3 = st.ray.diraction();
+                    let w: f64 = Self::background_weight(st.pdf, background, &dir);
+                    st.add(background.value(&dir) * w);
+                    break;
+                }
+            };
+            if rec.mat.null_collision() {
+                // dim and go on along the same ray, so neither the depth nor
+                // the pdf of the last bounce changes
+                let mut att: Color = Color::zero();
+                let mut scat: Ray = st.ray;
+                if !rec.mat.scatter(&st.ray, &rec, &mut att, &mut scat) {
+                    break;
+                }
+                st.throughput = Color::elemul(st.throughput, att);
+                t_min = Self::past(rec.t);
+                continue;
+            }
+            t_min = 0.001;
+            let w: f64 = self.emission_weight(st.pdf, &st.ray, &rec);
+            st.add(rec.mat.emitted(&st.ray, &rec) * w);
+            st.add(self.direct(&st.ray, &rec, background, world));
+
+            let r_in = st.ray;
+            let mut att: Color = Color::zero();
+            if !rec.mat.scatter(&r_in, &rec, &mut att, &mut st.ray) {
+                break;
+            }
+            st.pdf = Self::scatter_pdf(&r_in, &rec, &st.ray);
+            st.throughput = Color::elemul(st.throughput, att);
+            st.depth += 1;
+            let q: f64 = st
+                .throughput
+                .x()
+                .max(st.throughput.y())
+                .max(st.throughput.z());
+            match self.roulette(st.depth, q) {
+                Some(w) => st.throughput = st.throughput * w,
+                None => break,
+            }
+        }
+        st.radiance
+    }
+
+    // same path loop carrying hero wavelength samples instead of rgb; colors
+    // from textures, lights and the background are upsampled as they are met
+    pub fn li_spectral(&self, r: Ray, background: &Background, world: &BvhNode) -> Color {
+        let mut wl = Wavelengths::sample();
+        let mut ray: Ray = r;
+        ray.lambda = wl.hero();
+        let mut throughput = Spectrum::new(1.0);
+        let mut radiance = Spectrum::new(0.0);
+        let mut depth: i32 = 0;
+        let mut pdf: f64 = 0.0;
+        let mut t_min: f64 = 0.001;
+        while depth < self.max_depth {
+            let rec = match world.hit(ray, t_min, tools::INF) {
+                Some(rec) => rec,
+                None => {
+                    let dir: Vec3 = ray.diraction();
+                    let w: f64 = Self::background_weight(pdf, background, &dir);
+                    let bg: Color = background.value(&dir) * w;
+                    radiance += Spectrum::elemul(throughput, wl.upsample(bg));
+                    break;
+                }
+            };
+            if rec.mat.null_collision() {
+                let mut att: Color = Color::zero();
+                let mut scat: Ray = ray;
+                if !rec.mat.scatter(&ray, &rec, &mut att, &mut scat) {
+                    break;
+                }
+                throughput = Spectrum::elemul(throughput, wl.upsample(att));
+                t_min = Self::past(rec.t);
+                continue;
+            }
+            t_min = 0.001;
+            let emit: Color = rec.mat.emitted(&ray, &rec) * self.emission_weight(pdf, &ray, &rec);
+            let direct: Color = self.direct(&ray, &rec, background, world);
+            radiance += Spectrum::elemul(throughput, wl.upsample(emit + direct));
+
+            let r_in = ray;
+            let mut att: Color = Color::zero();
+            if !rec.mat.scatter(&r_in, &rec, &mut att, &mut ray) {
+                break;
+            }
+            pdf = Self::scatter_pdf(&r_in, &rec, &ray);
+            if rec.mat.dispersive() {
+                wl.terminate_secondary();
+            }
+            ray.lambda = wl.hero();
+            throughput = Spectrum::elemul(throughput, wl.upsample(att));
+            depth += 1;
+            match self.roulette(depth, throughput.max()) {
+                Some(w) => throughput = throughput * w,
+                None => break,
+            }
+        }
+        wl.to_rgb(&radiance)
+    }
+
+    // just beyond a null collision at t, relative so long rays don't stall
+    fn past(t: f64) -> f64 {
+        t + 1e-9 * t.abs().max(1.0)
+    }
+
+    // survive with probability of the throughput q, returning the weight that
+    // keeps the estimate unbiased or None when the path is killed
+    fn roulette(&self, depth: i32, q: f64) -> Option<f64> {
+        if depth < self.rr_depth {
+            return Some(1.0);
+        }
+        let q: f64 = tools::clamp(q, 0.0, 0.95);
+        if tools::randf(0.0, 1.0) >= q {
+            return None;
+        }
+        Some(1.0 / q)
+    }
+}
+
+// the old recursive estimator, kept as a reference for benchmarking
+pub fn ray_color_recursive(r: Ray, background: &Background, list: &BvhNode, depth: i32) -> Color {
+    if depth <= 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    match list.hit(r.clone(), 0.001, tools::INF) {
+        Some(rec) => {
+            let mut scat: Ray = Ray::new(Vec3::zero(), Vec3::zero(), 0.0);
+            let mut att: Color = Color::zero();
+            let emit = rec.mat.emitted(&r, &rec);
+            if rec
+                .mat
+                .scatter(&r.clone(), &rec.clone(), &mut att, &mut scat)
+            {
+                emit.clone()
+                    + Color::elemul(
+                        att.clone(),
+                        ray_color_recursive(scat.clone(), background, list, depth - 1),
+                    )
+            } else {
+                emit
+            }
+        }
+        None => background.value(&r.diraction()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::AreaLight;
+    use crate::light::PointLight;
+    use crate::material::Dielectric;
+    use crate::material::DiffuseLight;
+    use crate::material::Hair;
+    use crate::material::Lamber;
+    use crate::material::Material;
+    use crate::material::Metal;
+    use crate::material::RoughDielectric;
+    use crate::medium::Absorbing;
+    use crate::medium::MediumInterface;
+    use crate::planar::Quad;
+    use crate::shapes::Hitlist;
+    use crate::shapes::Sphere;
+
+    // a floor of mat seen at 45 degrees, lit only from the mirror direction
+    fn floor(mat: Arc<Material>) -> BvhNode {
+        let mut list = Hitlist::new();
+        list.add(Arc::new(Quad::new(
+            Vec3::new(-5.0, 0.0, -5.0),
+            Vec3::new(10.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 10.0),
+            mat,
+        )));
+        BvhNode::fnew(&mut list, 0.0, 1.0)
+    }
+
+    #[test]
+    fn test_mis_weights() {
+        let mat: Arc<Material> = Arc::new(Lamber::cnew(Color::new(0.5, 0.5, 0.5)));
+        let lamp = Arc::new(Quad::new(
+            Vec3::new(-1.0, 2.0, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Arc::new(DiffuseLight::cnew(Color::ones())),
+        ));
+        let mut list = Hitlist::new();
+        list.add(Arc::new(Quad::new(
+            Vec3::new(-5.0, 0.0, -5.0),
+            Vec3::new(10.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 10.0),
+            mat,
+        )));
+        list.add(lamp.clone());
+        // an emitter no light samples, off to the side
+        list.add(Arc::new(Quad::new(
+            Vec3::new(3.0, 1.0, -1.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Arc::new(DiffuseLight::cnew(Color::ones())),
+        )));
+        let world = BvhNode::fnew(&mut list, 0.0, 1.0);
+        let light: Arc<Light> = Arc::new(AreaLight::new(lamp));
+        let mut integ = Integrator::new(5, 5);
+        integ.lights = vec![light.clone()];
+
+        let r_in = Ray::new(Vec3::new(0.3, 1.0, 1.0), Vec3::new(0.0, -1.0, -1.0), 0.0);
+        let rec = world.hit(r_in, 0.001, tools::INF).unwrap();
+        let (mut lamp_hits, mut other_hits) = (0, 0);
+        for _ in 0..1000 {
+            // directions picked by the light and by scattering both
+            let (wi, _, _) = light.sample_li(&rec.p).unwrap();
+            let mut scat = r_in;
+            let mut att = Color::zero();
+            assert!(rec.mat.scatter(&r_in, &rec, &mut att, &mut scat));
+            for dir in [wi, scat.diraction().unit()].iter() {
+                let r = Ray::new(rec.p, *dir, 0.0);
+                let hit = match world.hit(r, 0.001, tools::INF) {
+                    Some(hit) => hit,
+                    None => continue,
+                };
+                let pdf: f64 = Integrator::scatter_pdf(&r_in, &rec, &r);
+                let w_emit: f64 = integ.emission_weight(pdf, &r, &hit);
+                if light.owns(&r, hit.t) {
+                    lamp_hits += 1;
+                    let w_light = Integrator::light_weight(&*light, &r_in, &rec, dir).unwrap();
+                    assert!((w_light + w_emit - 1.0).abs() < 1e-9);
+                } else if hit.mat.emitted(&r, &hit).x() > 0.0 {
+                    other_hits += 1;
+                    assert_eq!(w_emit, 1.0);
+                }
+            }
+        }
+        assert!(lamp_hits > 1000 && other_hits > 0);
+    }
+
+    #[test]
+    fn test_point_light() {
+        let mats: Vec<Arc<Material>> = vec![
+            Arc::new(Lamber::cnew(Color::new(0.5, 0.5, 0.5))),
+            Arc::new(Metal::new(Color::new(0.9, 0.9, 0.9), 0.3)),
+            Arc::new(RoughDielectric::cnew(1.5, 0.3)),
+            Arc::new(Hair::new(Color::new(0.2, 0.3, 0.5), 0.3)),
+        ];
+        for mat in mats {
+            let world = floor(mat);
+            // direct light only
+            let mut integ = Integrator::new(1, 1);
+            integ.lights = vec![Arc::new(PointLight::new(
+                Vec3::new(0.0, 1.0, -1.0),
+                Color::ones() * 10.0,
+            ))];
+            let r = Ray::new(Vec3::new(0.0, 1.0, 1.0), Vec3::new(0.0, -1.0, -1.0), 0.0);
+            let mut sum = Color::zero();
+            for _ in 0..64 {
+                sum += integ.li(r, &Color::zero(), &world);
+            }
+            assert!(sum.x() > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_absorbing_depth() {
+        // a ball of index 1 only passes light on, through two units of
+        // absorbing medium; the dimming collisions must not end the path
+        let mut list = Hitlist::new();
+        list.add(Arc::new(MediumInterface::new(
+            Arc::new(Sphere::new(
+                Vec3::zero(),
+                1.0,
+                Arc::new(Dielectric::new(1.0)),
+            )),
+            Arc::new(Absorbing::new(Color::new(0.5, 1.0, 2.0))),
+        )));
+        // out of the way, so the bvh has two leaves to tell apart
+        list.add(Arc::new(Sphere::new(
+            Vec3::new(10.0, 0.0, 0.0),
+            1.0,
+            Arc::new(Lamber::cnew(Color::zero())),
+        )));
+        let world = BvhNode::fnew(&mut list, 0.0, 1.0);
+        let integ = Integrator::new(3, 3);
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let n = 20000;
+        let mut sum = Color::zero();
+        for _ in 0..n {
+            sum += integ.li(r, &Color::ones(), &world);
+        }
+        let tr: Color = sum / n as f64;
+        assert!((tr.x() - (-1.0f64).exp()).abs() < 0.02);
+        assert!((tr.y() - (-2.0f64).exp()).abs() < 0.02);
+        assert!((tr.z() - (-4.0f64).exp()).abs() < 0.02);
+    }
+}
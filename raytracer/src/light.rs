@@ -0,0 +1,254 @@
+use crate::color::Color;
+use crate::ies::IesProfile;
+use crate::planar::Planar;
+use crate::ray::Ray;
+use crate::tools;
+use crate::vec3::Onb;
+use crate::vec3::Vec3;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+// lights the integrator samples with shadow rays instead of waiting for a
+// scattered ray to run into them, which a point or a direction never does.
+// they reach a surface only through Material::eval, so perfect mirrors and
+// smooth interfaces (Dielectric, Metal without fuzz and the boundary of a
+// Subsurface) stay dark under point, spot and directional lights; only area
+// lights and the background light those, by being hit
+pub trait Light: Debug + Send + Sync {
+    // unit direction from p towards the light, the distance to it and the
+    // radiance arriving at p divided by the sampling pdf
+    fn sample_li(&self, p: &Vec3) -> Option<(Vec3, f64, Color)>;
+    // solid angle density of sample_li choosing dir from p, 0 for the delta
+    // lights that no scattered ray can hit
+    fn pdf_li(&self, p: &Vec3, dir: &Vec3) -> f64 {
+        0.0
+    }
+    // whether r meets the light's own surface at t, so emission found there
+    // came from this light and not from some other glowing surface
+    fn owns(&self, r: &Ray, t: f64) -> bool {
+        false
+    }
+}
+
+// a measured distribution aimed along the frame's w, scaling intensity by direction
+#[derive(Debug, Clone)]
+pub struct Goniometric {
+    profile: Arc<IesProfile>,
+    frame: Onb,
+}
+
+impl Goniometric {
+    pub fn new(profile: Arc<IesProfile>, pos: Vec3, at: Vec3) -> Self {
+        Self {
+            profile,
+            frame: Onb::new(at - pos),
+        }
+    }
+    // towards the unit direction leaving the light
+    fn scale(&self, dir: &Vec3) -> f64 {
+        self.profile.value(&self.frame, dir)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PointLight {
+    pos: Vec3,
+    intensity: Color, // radiant intensity, falls off with the squared distance
+    ies: Option<Goniometric>,
+}
+
+impl PointLight {
+    pub fn new(pos: Vec3, intensity: Color) -> Self {
+        Self {
+            pos,
+            intensity,
+            ies: None,
+        }
+    }
+    // intensity is reached at the brightest angle of the profile
+    pub fn inew(pos: Vec3, at: Vec3, intensity: Color, profile: Arc<IesProfile>) -> Self {
+        Self {
+            pos,
+            intensity,
+            ies: Some(Goniometric::new(profile, pos, at)),
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: &Vec3) -> Option<(Vec3, f64, Color)> {
+        let d: Vec3 = self.pos - *p;
+        let dist2: f64 = d.squared_length();
+        if dist2 <= 0.0 {
+            return None;
+        }
+        let dist: f64 = dist2.sqrt();
+        let wi: Vec3 = d / dist;
+        let scale: f64 = match &self.ies {
+            Some(ies) => ies.scale(&-wi),
+            None => 1.0,
+        };
+        if scale <= 0.0 {
+            return None;
+        }
+        Some((wi, dist, self.intensity * (scale / dist2)))
+    }
+}
+
+// a point light restricted to a cone, fading smoothly between the two angles
+#[derive(Debug, Clone)]
+pub struct SpotLight {
+    pos: Vec3,
+    dir: Vec3,
+    intensity: Color,
+    cos_total: f64,
+    cos_start: f64,
+    ies: Option<Goniometric>,
+}
+
+impl SpotLight {
+    // angles in degrees from the axis: full brightness up to falloff_start, dark past total
+    pub fn new(pos: Vec3, at: Vec3, intensity: Color, total: f64, falloff_start: f64) -> Self {
+        Self {
+            pos,
+            dir: (at - pos).unit(),
+            intensity,
+            cos_total: tools::dtr(total).cos(),
+            cos_start: tools::dtr(falloff_start.min(total)).cos(),
+            ies: None,
+        }
+    }
+    // a profile shaped by the cone on top, aimed the same way
+    pub fn inew(
+        pos: Vec3,
+        at: Vec3,
+        intensity: Color,
+        total: f64,
+        falloff_start: f64,
+        profile: Arc<IesProfile>,
+    ) -> Self {
+        Self {
+            ies: Some(Goniometric::new(profile, pos, at)),
+            ..Self::new(pos, at, intensity, total, falloff_start)
+        }
+    }
+
+    fn falloff(&self, cos_t: f64) -> f64 {
+        if cos_t <= self.cos_total {
+            return 0.0;
+        }
+        if cos_t >= self.cos_start {
+            return 1.0;
+        }
+        let t: f64 = (cos_t - self.cos_total) / (self.cos_start - self.cos_total);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: &Vec3) -> Option<(Vec3, f64, Color)> {
+        let d: Vec3 = self.pos - *p;
+        let dist2: f64 = d.squared_length();
+        if dist2 <= 0.0 {
+            return None;
+        }
+        let dist: f64 = dist2.sqrt();
+        let wi: Vec3 = d / dist;
+        let mut fall: f64 = self.falloff(-wi * self.dir);
+        if let Some(ies) = &self.ies {
+            fall *= ies.scale(&-wi);
+        }
+        if fall <= 0.0 {
+            return None;
+        }
+        Some((wi, dist, self.intensity * (fall / dist2)))
+    }
+}
+
+// light from infinitely far away, e.g. the sun; a nonzero angular radius
+// spreads it over a small cone and softens the shadows
+#[derive(Debug, Clone)]
+pub struct DirectionalLight {
+    dir: Vec3, // towards the light
+    irradiance: Color,
+    cos_radius: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(dir: Vec3, irradiance: Color) -> Self {
+        Self::rnew(dir, irradiance, 0.0)
+    }
+    // radius in degrees
+    pub fn rnew(dir: Vec3, irradiance: Color, radius: f64) -> Self {
+        Self {
+            dir: dir.unit(),
+            irradiance,
+            cos_radius: tools::dtr(radius).cos(),
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, p: &Vec3) -> Option<(Vec3, f64, Color)> {
+        if self.cos_radius >= 1.0 {
+            return Some((self.dir, tools::INF, self.irradiance));
+        }
+        // uniform in the cone, radiance over the cone's solid angle cancels the pdf
+        let cos_t: f64 = 1.0 - tools::randf(0.0, 1.0) * (1.0 - self.cos_radius);
+        let sin_t: f64 = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+        let phi: f64 = 2.0 * tools::PI * tools::randf(0.0, 1.0);
+        let local: Vec3 = Vec3::new(sin_t * phi.cos(), sin_t * phi.sin(), cos_t);
+        Some((Onb::new(self.dir).local(local), tools::INF, self.irradiance))
+    }
+}
+
+// an emitting flat shape sampled by area; the shape also has to be in the
+// world, where scattered rays meet it and are weighted against these samples
+#[derive(Debug, Clone)]
+pub struct AreaLight {
+    shape: Arc<Planar>,
+}
+
+impl AreaLight {
+    pub fn new(shape: Arc<Planar>) -> Self {
+        Self { shape }
+    }
+    // area density turned into solid angle seen from dist away along wi
+    fn pdf(&self, wi: &Vec3, dist: f64) -> f64 {
+        let cos_l: f64 = (*wi * self.shape.normal()).abs();
+        if cos_l < 1e-8 {
+            return 0.0;
+        }
+        dist * dist / (cos_l * self.shape.area())
+    }
+}
+
+impl Light for AreaLight {
+    fn sample_li(&self, p: &Vec3) -> Option<(Vec3, f64, Color)> {
+        let d: Vec3 = self.shape.sample() - *p;
+        let dist: f64 = d.length();
+        // hit the shape itself for the material and uv at the sampled point
+        let r: Ray = Ray::new(*p, d, 0.0);
+        let rec = self.shape.hit(r, 1e-6, 1.0 + 1e-6)?;
+        let wi: Vec3 = d / dist;
+        let pdf: f64 = self.pdf(&wi, dist);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((wi, dist, rec.mat.emitted(&r, &rec) / pdf))
+    }
+
+    fn pdf_li(&self, p: &Vec3, dir: &Vec3) -> f64 {
+        match self.shape.hit(Ray::new(*p, *dir, 0.0), 0.001, tools::INF) {
+            Some(rec) => self.pdf(&dir.unit(), rec.t * dir.length()),
+            None => 0.0,
+        }
+    }
+
+    fn owns(&self, r: &Ray, t: f64) -> bool {
+        match self.shape.hit(*r, 0.001, tools::INF) {
+            Some(rec) => (rec.t - t).abs() <= 1e-6 * t.max(1.0),
+            None => false,
+        }
+    }
+}
@@ -7,34 +7,81 @@ use std::clone;
 use std::sync::mpsc::channel;
 use threadpool::ThreadPool;
 
+pub mod animation;
+pub mod background;
 pub mod camera;
 pub mod color;
+pub mod csg;
+pub mod curve;
+pub mod heightfield;
+pub mod ies;
+pub mod integrator;
+pub mod light;
 pub mod material;
+pub mod medium;
+pub mod microfacet;
 pub mod perlin;
+pub mod planar;
+pub mod quadric;
 pub mod ray;
+pub mod sdf;
 pub mod shapes;
+pub mod spectrum;
+#[cfg(test)]
+mod test_util;
 pub mod texture;
 pub mod tools;
+pub mod transform;
 pub mod vec3;
+use animation::Animation;
+use animation::CameraKey;
+use animation::CameraPath;
+use background::Background;
 use camera::Camera;
+use camera::Viewer;
 use color::Color;
+use csg::Csg;
+use curve::Curve;
+use curve::CurveKind;
+use heightfield::Heightfield;
+use integrator::Integrator;
+use light::Light;
+use material::Coated;
 use material::Dielectric;
 use material::DiffuseLight;
+use material::Hair;
 use material::Lamber;
 use material::Material;
 use material::Metal;
+use material::MixMaterial;
 use material::Neg;
+use material::Principled;
+use material::RoughConductor;
+use material::RoughDielectric;
+use planar::Quad;
+use planar::Triangle;
 use ray::Ray;
+use sdf::Sdf;
+use sdf::SdfShape;
+use sdf::SdfSphere;
 use shapes::Hitlist;
 use shapes::Hitrec;
 use shapes::Hittable;
 use shapes::MovingSphere;
 use shapes::Sphere;
+use spectrum::Ior;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Instant;
 use tools::randf;
+use transform::Animated;
+use transform::AnimatedTransform;
+use transform::Keyframe;
+use transform::Quat;
+use transform::Transform;
+use transform::Transformed;
 use vec3::Vec3;
 
 struct World {
@@ -50,29 +97,6 @@ impl World {
     }
 }
 
-pub fn ray_color(r: Ray, background: &Color, list: &shapes::BvhNode, depth: i32) -> Color {
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
-    }
-    match list.hit(r.clone(), 0.001, tools::INF) {
-        Some(rec) => {
-            let mut scat: Ray = Ray::new(Vec3::zero(), Vec3::zero(), 0.0);
-            let mut att: Color = Color::zero();
-            let emit = rec.mat.emitted(rec.u, rec.v, &rec.p);
-            if rec.mat.scatter(r.clone(), rec.clone(), &mut att, &mut scat) {
-                return emit.clone()
-                    + Color::elemul(
-                        att.clone(),
-                        ray_color(scat.clone(), background, list, depth - 1),
-                    );
-            } else {
-                emit
-            }
-        }
-        None => *background,
-    }
-}
-
 pub fn random_scene() -> Hitlist {
     let mut list: Hitlist = Hitlist::new();
 
@@ -218,13 +242,19 @@ pub fn simple_light() -> Hitlist {
     list
 }
 
-pub fn cornell_box() -> Hitlist {
+// walls and ceiling light of the cornell box, without anything inside
+pub fn cornell_walls() -> Hitlist {
     let mut list = Hitlist::new();
 
     let red = Arc::new(Lamber::cnew(Color::new(0.65, 0.05, 0.05)));
     let white = Arc::new(Lamber::cnew(Color::new(0.73, 0.73, 0.73)));
     let green = Arc::new(Lamber::cnew(Color::new(0.12, 0.45, 0.15)));
-    let light = Arc::new(DiffuseLight::cnew(Color::new(7.0, 7.0, 7.0)));
+    // one sided so the ceiling light only shines into the box
+    let light = Arc::new(DiffuseLight::snew(
+        Arc::new(texture::SolidColor::new(Color::new(7.0, 7.0, 7.0))),
+        1.0,
+        false,
+    ));
 
     let arc_1 = Arc::new(shapes::YzRect::new(
         0.0,
@@ -242,14 +272,14 @@ pub fn cornell_box() -> Hitlist {
         0.0,
         red.clone(),
     ));
-    let arc_3 = Arc::new(shapes::XzRect::new(
+    let arc_3 = Arc::new(shapes::FlipFace::new(Arc::new(shapes::XzRect::new(
         113.0,
         443.0,
         127.0,
         432.0,
         554.0,
         light.clone(),
-    ));
+    ))));
     let arc_4 = Arc::new(shapes::XzRect::new(
         0.0,
         555.0,
@@ -275,15 +305,29 @@ pub fn cornell_box() -> Hitlist {
         white.clone(),
     ));
 
+    list.add(arc_1);
+    list.add(arc_2);
+    list.add(arc_3);
+    list.add(arc_4);
+    list.add(arc_5);
+    list.add(arc_6);
+
+    list
+}
+
+pub fn cornell_box() -> Hitlist {
+    let mut list = cornell_walls();
+
+    let white = Arc::new(Lamber::cnew(Color::new(0.73, 0.73, 0.73)));
+
     let arc_7 = Arc::new(shapes::Boxes::new(
         Vec3::new(0.0, 0.0, 0.0),
         Vec3::new(165.0, 330.0, 165.0),
         white.clone(),
     ));
-    let arc_7_1 = Arc::new(shapes::RotateY::new(arc_7.clone(), 15.0));
-    let arc_7_2 = Arc::new(shapes::Translate::new(
-        arc_7_1.clone(),
-        Vec3::new(265.0, 0.0, 295.0),
+    let arc_7_2 = Arc::new(Transformed::new(
+        arc_7.clone(),
+        Transform::rotate_y(15.0).then(&Transform::translate(Vec3::new(265.0, 0.0, 295.0))),
     ));
     let arc_7_3 = Arc::new(shapes::ConstantMedium::cnew(
         arc_7_2.clone(),
@@ -296,10 +340,9 @@ pub fn cornell_box() -> Hitlist {
         Vec3::new(165.0, 165.0, 165.0),
         white.clone(),
     ));
-    let arc_8_1 = Arc::new(shapes::RotateY::new(arc_8.clone(), -18.0));
-    let arc_8_2 = Arc::new(shapes::Translate::new(
-        arc_8_1.clone(),
-        Vec3::new(130.0, 0.0, 65.0),
+    let arc_8_2 = Arc::new(Transformed::new(
+        arc_8.clone(),
+        Transform::rotate_y(-18.0).then(&Transform::translate(Vec3::new(130.0, 0.0, 65.0))),
     ));
     let arc_8_3 = Arc::new(shapes::ConstantMedium::cnew(
         arc_8_2.clone(),
@@ -307,18 +350,557 @@ pub fn cornell_box() -> Hitlist {
         Color::ones(),
     ));
 
-    list.add(arc_1);
-    list.add(arc_2);
-    list.add(arc_3);
-    list.add(arc_4);
-    list.add(arc_5);
-    list.add(arc_6);
     list.add(arc_7_3);
     list.add(arc_8_3);
 
     list
 }
 
+pub fn cornell_fog() -> Hitlist {
+    let mut list = cornell_box();
+
+    // thin forward scattering fog below the light, shows a glow around it
+    let room = Arc::new(shapes::Boxes::new(
+        Vec3::new(1.0, 1.0, 1.0),
+        Vec3::new(554.0, 553.0, 554.0),
+        Arc::new(Neg::new()),
+    ));
+    list.add(Arc::new(shapes::ConstantMedium::pnew(
+        room,
+        0.0015,
+        Arc::new(material::HenyeyGreenstein::cnew(Color::ones(), 0.7)),
+    )));
+
+    list
+}
+
+pub fn cornell_smoke() -> Hitlist {
+    let mut list = cornell_walls();
+
+    // a turbulent cloud floating above a baked column of smoke
+    let cloud = Arc::new(Sphere::new(
+        Vec3::new(278.0, 330.0, 278.0),
+        130.0,
+        Arc::new(Neg::new()),
+    ));
+    list.add(Arc::new(medium::HeteroMedium::cnew(
+        cloud,
+        Arc::new(medium::NoiseDensity::new(0.05, 0.015)),
+        Color::ones(),
+    )));
+
+    let lo = Vec3::new(180.0, 0.0, 180.0);
+    let hi = Vec3::new(380.0, 200.0, 380.0);
+    let column = |p: &Vec3| -> f64 {
+        let dx = (p.x() - 280.0) / 100.0;
+        let dz = (p.z() - 280.0) / 100.0;
+        let r2 = dx * dx + dz * dz;
+        0.05 * (1.0 - r2).max(0.0) * (1.0 - p.y() / 200.0)
+    };
+    let smoke = Arc::new(shapes::Boxes::new(lo, hi, Arc::new(Neg::new())));
+    list.add(Arc::new(medium::HeteroMedium::cnew(
+        smoke,
+        Arc::new(medium::GridDensity::fnew(lo, hi, 32, &column)),
+        Color::new(0.6, 0.6, 0.6),
+    )));
+
+    list
+}
+
+pub fn cornell_wax() -> Hitlist {
+    let mut list = cornell_walls();
+
+    // marble, skin and wax, differing in albedo and how far each channel travels
+    let looks = [
+        (Color::new(0.99, 0.99, 0.99), Color::new(20.0, 20.0, 20.0)),
+        (Color::new(0.95, 0.8, 0.7), Color::new(36.0, 14.0, 8.0)),
+        (Color::new(0.98, 0.85, 0.5), Color::new(30.0, 22.0, 10.0)),
+    ];
+    for (i, (albedo, mfp)) in looks.iter().enumerate() {
+        let ball = Arc::new(Sphere::new(
+            Vec3::new(130.0 + 150.0 * i as f64, 90.0, 300.0),
+            70.0,
+            Arc::new(Neg::new()),
+        ));
+        list.add(Arc::new(medium::Subsurface::anew(
+            ball, *albedo, *mfp, 1.4, 0.0,
+        )));
+    }
+
+    list
+}
+
+pub fn delta_lights() -> (Hitlist, Vec<Arc<Light>>) {
+    let mut list = Hitlist::new();
+    list.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lamber::cnew(Color::new(0.5, 0.5, 0.5))),
+    )));
+    list.add(Arc::new(Sphere::new(
+        Vec3::new(-2.5, 1.0, 0.0),
+        1.0,
+        Arc::new(Principled::cnew(Color::new(0.8, 0.2, 0.1), 0.0, 0.4)),
+    )));
+    list.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 1.0, 0.0),
+        1.0,
+        Arc::new(RoughConductor::gold(0.3)),
+    )));
+    list.add(Arc::new(Sphere::new(
+        Vec3::new(2.5, 1.0, 0.0),
+        1.0,
+        Arc::new(Lamber::new(Arc::new(texture::NoiseTexture::new(4.0)))),
+    )));
+
+    let lights: Vec<Arc<Light>> = vec![
+        Arc::new(light::SpotLight::new(
+            Vec3::new(0.0, 8.0, 2.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Color::new(60.0, 55.0, 45.0),
+            30.0,
+            20.0,
+        )),
+        Arc::new(light::PointLight::new(
+            Vec3::new(-4.0, 3.0, 4.0),
+            Color::new(4.0, 6.0, 10.0),
+        )),
+        Arc::new(light::DirectionalLight::rnew(
+            Vec3::new(1.0, 1.0, -1.0),
+            Color::new(0.3, 0.25, 0.2),
+            2.0,
+        )),
+    ];
+    (list, lights)
+}
+
+// downlights close to a wall draw the scallops of their measured profile
+pub fn ies_wall() -> (Hitlist, Vec<Arc<Light>>) {
+    let mut list = Hitlist::new();
+    let white = Arc::new(Lamber::cnew(Color::new(0.73, 0.73, 0.73)));
+    list.add(Arc::new(shapes::XzRect::new(
+        -10.0,
+        10.0,
+        -10.0,
+        10.0,
+        0.0,
+        white.clone(),
+    )));
+    list.add(Arc::new(shapes::XyRect::new(
+        -10.0,
+        10.0,
+        0.0,
+        6.0,
+        -1.0,
+        white.clone(),
+    )));
+
+    let profile = Arc::new(ies::IesProfile::new(Path::new("downlight.ies")));
+    let mut lights: Vec<Arc<Light>> = Vec::new();
+    for i in 0..3 {
+        let pos = Vec3::new(-4.0 + 4.0 * i as f64, 5.0, -0.4);
+        lights.push(Arc::new(light::PointLight::inew(
+            pos,
+            pos - Vec3::new(0.0, 1.0, 0.0),
+            Color::new(30.0, 27.0, 22.0),
+            profile.clone(),
+        )));
+    }
+    (list, lights)
+}
+
+// a box spinning and sliding while the shutter is open, next to a ball
+// that swells as it rises
+pub fn cornell_motion() -> Hitlist {
+    let mut list = cornell_walls();
+
+    let white = Arc::new(Lamber::cnew(Color::new(0.73, 0.73, 0.73)));
+    let tall = Arc::new(shapes::Boxes::new(
+        Vec3::new(-82.5, 0.0, -82.5),
+        Vec3::new(82.5, 330.0, 82.5),
+        white.clone(),
+    ));
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    list.add(Arc::new(Animated::new(
+        tall,
+        AnimatedTransform::new(vec![
+            Keyframe::rnew(0.0, Vec3::new(370.0, 0.0, 350.0), Quat::rotation(up, 0.0)),
+            Keyframe::rnew(1.0, Vec3::new(330.0, 0.0, 370.0), Quat::rotation(up, 60.0)),
+        ]),
+    )));
+
+    let ball = Arc::new(Sphere::new(
+        Vec3::zero(),
+        1.0,
+        Arc::new(Principled::cnew(Color::new(0.8, 0.3, 0.1), 0.0, 0.3)),
+    ));
+    list.add(Arc::new(Animated::new(
+        ball,
+        AnimatedTransform::new(vec![
+            Keyframe::new(
+                0.0,
+                Vec3::new(150.0, 70.0, 200.0),
+                Quat::identity(),
+                Vec3::new(70.0, 70.0, 70.0),
+            ),
+            Keyframe::new(
+                1.0,
+                Vec3::new(150.0, 160.0, 200.0),
+                Quat::identity(),
+                Vec3::new(90.0, 60.0, 90.0),
+            ),
+        ]),
+    )));
+
+    list
+}
+
+// the quadric primitives standing on a checkered floor
+pub fn quadrics() -> Hitlist {
+    let mut list = Hitlist::new();
+
+    let c1 = Color::new(0.2, 0.3, 0.1);
+    let c2 = Color::new(0.9, 0.9, 0.9);
+    let mat_g = Arc::new(Lamber::new(Arc::new(texture::CheckerTexture::cnew(c1, c2))));
+    list.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        mat_g,
+    )));
+
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    list.add(Arc::new(quadric::Cylinder::new(
+        Vec3::new(-4.0, 0.0, 0.0),
+        Vec3::new(-4.0, 2.0, 0.0),
+        0.8,
+        true,
+        Arc::new(RoughConductor::aluminium(0.2)),
+    )));
+    list.add(Arc::new(quadric::Cone::new(
+        Vec3::new(-1.5, 0.0, 0.0),
+        1.0,
+        Vec3::new(-1.5, 2.2, 0.0),
+        0.0,
+        true,
+        Arc::new(Principled::cnew(Color::new(0.8, 0.1, 0.1), 0.0, 0.3)),
+    )));
+    list.add(Arc::new(quadric::Cone::new(
+        Vec3::new(1.0, 0.0, 0.0),
+        0.9,
+        Vec3::new(1.0, 1.5, 0.0),
+        0.5,
+        true,
+        Arc::new(Lamber::new(Arc::new(texture::NoiseTexture::new(4.0)))),
+    )));
+    list.add(Arc::new(quadric::Torus::new(
+        Vec3::new(3.8, 1.3, 0.0),
+        Vec3::new(0.0, 0.5, 1.0),
+        1.0,
+        0.3,
+        Arc::new(RoughConductor::gold(0.2)),
+    )));
+    list.add(Arc::new(planar::Disk::new(
+        Vec3::new(-2.5, 0.01, 2.5),
+        up,
+        0.8,
+        Arc::new(Lamber::cnew(Color::new(0.1, 0.2, 0.6))),
+    )));
+    list.add(Arc::new(planar::Disk::anew(
+        Vec3::new(1.5, 0.01, 2.5),
+        up,
+        0.9,
+        0.5,
+        Arc::new(Lamber::cnew(Color::new(0.9, 0.6, 0.1))),
+    )));
+
+    list
+}
+
+// the cornell box built from quads, its lights sampled by area
+pub fn cornell_area() -> (Hitlist, Vec<Arc<Light>>) {
+    let mut list = Hitlist::new();
+
+    let red = Arc::new(Lamber::cnew(Color::new(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lamber::cnew(Color::new(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lamber::cnew(Color::new(0.12, 0.45, 0.15)));
+    let x = Vec3::new(555.0, 0.0, 0.0);
+    let y = Vec3::new(0.0, 555.0, 0.0);
+    let z = Vec3::new(0.0, 0.0, 555.0);
+    list.add(Arc::new(Quad::new(x, y, z, green)));
+    list.add(Arc::new(Quad::new(Vec3::zero(), y, z, red)));
+    list.add(Arc::new(Quad::new(Vec3::zero(), x, z, white.clone())));
+    list.add(Arc::new(Quad::new(y, x, z, white.clone())));
+    list.add(Arc::new(Quad::new(z, x, y, white.clone())));
+
+    // a ceiling panel shining down and a small round lamp on the back wall
+    let panel = Arc::new(Quad::new(
+        Vec3::new(113.0, 554.0, 127.0),
+        Vec3::new(330.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 305.0),
+        Arc::new(DiffuseLight::snew(
+            Arc::new(texture::SolidColor::new(Color::new(7.0, 7.0, 7.0))),
+            1.0,
+            false,
+        )),
+    ));
+    let lamp = Arc::new(planar::Disk::new(
+        Vec3::new(420.0, 420.0, 554.0),
+        Vec3::new(0.0, 0.0, -1.0),
+        40.0,
+        Arc::new(DiffuseLight::tnew(2700.0, 15.0, false)),
+    ));
+    list.add(panel.clone());
+    list.add(lamp.clone());
+
+    list.add(Arc::new(Transformed::new(
+        Arc::new(shapes::Boxes::new(
+            Vec3::zero(),
+            Vec3::new(165.0, 330.0, 165.0),
+            white.clone(),
+        )),
+        Transform::rotate_y(15.0).then(&Transform::translate(Vec3::new(265.0, 0.0, 295.0))),
+    )));
+    list.add(Arc::new(Sphere::new(
+        Vec3::new(190.0, 90.0, 190.0),
+        90.0,
+        Arc::new(RoughConductor::copper(0.3)),
+    )));
+    list.add(Arc::new(Triangle::new(
+        Vec3::new(1.0, 150.0, 100.0),
+        Vec3::new(1.0, 350.0, 200.0),
+        Vec3::new(1.0, 150.0, 300.0),
+        Arc::new(Lamber::cnew(Color::new(0.9, 0.8, 0.2))),
+    )));
+
+    let lights: Vec<Arc<Light>> = vec![
+        Arc::new(light::AreaLight::new(panel)),
+        Arc::new(light::AreaLight::new(lamp)),
+    ];
+    (list, lights)
+}
+
+// carved shapes: the classic cube, sphere and drilled cylinders, a glass
+// lens from two spheres and a ball with a box cut out of it
+pub fn csg_shapes() -> Hitlist {
+    let mut list = Hitlist::new();
+
+    let c1 = Color::new(0.2, 0.3, 0.1);
+    let c2 = Color::new(0.9, 0.9, 0.9);
+    let mat_g = Arc::new(Lamber::new(Arc::new(texture::CheckerTexture::cnew(c1, c2))));
+    list.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        mat_g,
+    )));
+
+    let red = Arc::new(Principled::cnew(Color::new(0.8, 0.1, 0.1), 0.0, 0.3));
+    let blue = Arc::new(Principled::cnew(Color::new(0.1, 0.2, 0.8), 0.0, 0.3));
+    let ct = Vec3::new(-3.0, 1.2, 0.0);
+    let cube: Arc<Hittable> = Arc::new(shapes::Boxes::new(
+        ct - Vec3::ones(),
+        ct + Vec3::ones(),
+        red.clone(),
+    ));
+    let ball: Arc<Hittable> = Arc::new(Sphere::new(ct, 1.35, blue.clone()));
+    let mut body: Arc<Hittable> = Arc::new(Csg::intersection(cube, ball));
+    let axes = [
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(0.0, 0.0, 1.0),
+    ];
+    for axis in axes.iter() {
+        let drill = Arc::new(quadric::Cylinder::new(
+            ct - *axis * 1.5,
+            ct + *axis * 1.5,
+            0.6,
+            true,
+            Arc::new(RoughConductor::gold(0.2)),
+        ));
+        body = Arc::new(Csg::difference(body, drill));
+    }
+    list.add(body);
+
+    let lens = Csg::intersection(
+        Arc::new(Sphere::new(
+            Vec3::new(0.0, 1.2, 2.6),
+            3.0,
+            Arc::new(Dielectric::new(1.5)),
+        )),
+        Arc::new(Sphere::new(
+            Vec3::new(0.0, 1.2, -2.6),
+            3.0,
+            Arc::new(Dielectric::new(1.5)),
+        )),
+    );
+    list.add(Arc::new(lens));
+
+    let bite = Csg::difference(
+        Arc::new(Sphere::new(
+            Vec3::new(3.0, 1.2, 0.0),
+            1.2,
+            Arc::new(RoughConductor::aluminium(0.1)),
+        )),
+        Arc::new(shapes::Boxes::new(
+            Vec3::new(3.0, 1.2, 0.0),
+            Vec3::new(4.5, 2.7, 1.5),
+            blue,
+        )),
+    );
+    list.add(Arc::new(bite));
+
+    list
+}
+
+// distance field shapes: a blob of melted balls, a twisted bar, a grid of
+// rings and a rounded box with a scoop taken out of it
+pub fn sdf_shapes() -> Hitlist {
+    let mut list = Hitlist::new();
+
+    let c1 = Color::new(0.2, 0.3, 0.1);
+    let c2 = Color::new(0.9, 0.9, 0.9);
+    let mat_g = Arc::new(Lamber::new(Arc::new(texture::CheckerTexture::cnew(c1, c2))));
+    list.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        mat_g,
+    )));
+
+    let mut blob: Arc<Sdf> = Arc::new(SdfSphere::new(Vec3::new(-3.0, 1.0, 0.0), 0.8));
+    for ct in [Vec3::new(-2.3, 1.6, 0.3), Vec3::new(-3.5, 1.8, -0.4)].iter() {
+        let ball = Arc::new(SdfSphere::new(*ct, 0.5));
+        blob = Arc::new(sdf::SmoothUnion::new(blob, ball, 0.6));
+    }
+    list.add(Arc::new(SdfShape::new(
+        blob,
+        Arc::new(Principled::cnew(Color::new(0.1, 0.6, 0.3), 0.0, 0.2)),
+    )));
+
+    let bar = Arc::new(sdf::SdfBox::new(
+        Vec3::zero(),
+        Vec3::new(0.45, 1.0, 0.45),
+        0.05,
+    ));
+    list.add(Arc::new(Transformed::new(
+        Arc::new(SdfShape::new(
+            Arc::new(sdf::Twist::new(bar, 1.2)),
+            Arc::new(RoughConductor::copper(0.25)),
+        )),
+        Transform::translate(Vec3::new(0.0, 1.0, 0.0)),
+    )));
+
+    let ring = Arc::new(sdf::SdfTorus::new(Vec3::zero(), 0.3, 0.08));
+    let rings = sdf::Repeat::new(ring, Vec3::new(0.8, 0.0, 0.8), Vec3::new(1.0, 0.0, 1.0));
+    list.add(Arc::new(Transformed::new(
+        Arc::new(SdfShape::new(
+            Arc::new(rings),
+            Arc::new(RoughConductor::gold(0.2)),
+        )),
+        Transform::translate(Vec3::new(3.0, 0.08, 0.0)),
+    )));
+
+    let cube = Arc::new(sdf::SdfBox::new(
+        Vec3::new(0.5, 0.6, 2.5),
+        Vec3::new(0.6, 0.6, 0.6),
+        0.1,
+    ));
+    let scoop = Arc::new(SdfSphere::new(Vec3::new(0.5, 1.3, 2.5), 0.6));
+    list.add(Arc::new(SdfShape::new(
+        Arc::new(sdf::SmoothSubtraction::new(cube, scoop, 0.15)),
+        Arc::new(Dielectric::new(1.5)),
+    )));
+
+    list
+}
+
+// rolling hills from heightmap.png, or from noise when there is no such
+// image, with a lake filling the valleys
+pub fn terrain() -> Hitlist {
+    let mut list = Hitlist::new();
+
+    let corner = Vec3::new(-20.0, 0.0, -20.0);
+    let size = Vec3::new(40.0, 8.0, 40.0);
+    let mat_g = Arc::new(Lamber::cnew(Color::new(0.35, 0.45, 0.2)));
+    let ground = match Heightfield::open(Path::new("heightmap.png"), corner, size, mat_g.clone()) {
+        Ok(field) => field,
+        Err(e) => {
+            eprintln!("{}, using noise hills instead", e);
+            let noise = perlin::Perlin::new();
+            let hills = move |u: f64, v: f64| {
+                let p = Vec3::new(u * 6.0, 0.0, v * 6.0);
+                tools::clamp(noise.turb(&p, 7) * 0.8, 0.0, 1.0)
+            };
+            Heightfield::fnew(hills, 256, 256, corner, size, mat_g)
+        }
+    };
+    list.add(Arc::new(ground));
+
+    list.add(Arc::new(Quad::new(
+        Vec3::new(-20.0, 1.2, 20.0),
+        Vec3::new(40.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -40.0),
+        Arc::new(Dielectric::new(1.33)),
+    )));
+
+    list
+}
+
+// a ball of fur sitting in a patch of grass, both made of curves
+pub fn fur_and_grass() -> Hitlist {
+    let mut list = Hitlist::new();
+    let up = Vec3::new(0.0, 1.0, 0.0);
+
+    let soil = Arc::new(Lamber::cnew(Color::new(0.3, 0.22, 0.12)));
+    list.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        soil,
+    )));
+
+    for _ in 0..3000 {
+        let base = Vec3::new(randf(-3.0, 3.0), 0.0, randf(-3.0, 1.5));
+        let hgt: f64 = randf(0.3, 0.7);
+        let phi: f64 = randf(0.0, 2.0 * tools::PI);
+        let lean = Vec3::new(phi.cos(), 0.0, phi.sin()) * hgt;
+        let cp = [
+            base,
+            base + up * (hgt / 3.0),
+            base + up * (hgt * 2.0 / 3.0) + lean * 0.2,
+            base + up * hgt + lean * 0.5,
+        ];
+        let green = Color::new(randf(0.1, 0.2), randf(0.35, 0.5), randf(0.05, 0.1));
+        let blade = Arc::new(Lamber::cnew(green));
+        for piece in Curve::split(cp, 0.03, 0.002, CurveKind::Ribbon, blade, 2) {
+            list.add(piece);
+        }
+    }
+
+    let ct = Vec3::new(0.0, 0.7, 0.0);
+    let rad: f64 = 0.5;
+    list.add(Arc::new(Sphere::new(
+        ct,
+        rad,
+        Arc::new(Lamber::cnew(Color::new(0.15, 0.08, 0.04))),
+    )));
+    let fur = Arc::new(Hair::melanin(1.3, 0.15));
+    for _ in 0..6000 {
+        let n: Vec3 = vec3::rand_in_unit_sphere().unit();
+        let root = ct + n * rad;
+        let len: f64 = randf(0.15, 0.25);
+        // strands droop under their own weight
+        let cp = [
+            root,
+            root + n * (len / 3.0),
+            root + n * (len * 2.0 / 3.0) - up * (len * 0.15),
+            root + n * len - up * (len * 0.4),
+        ];
+        for piece in Curve::split(cp, 0.006, 0.001, CurveKind::Ribbon, fur.clone(), 2) {
+            list.add(piece);
+        }
+    }
+
+    list
+}
+
 pub fn final_scene() -> Hitlist {
     let mut ground = Hitlist::new();
     let mat_g = Arc::new(Lamber::cnew(Color::new(0.48, 0.83, 0.53)));
@@ -345,15 +927,14 @@ pub fn final_scene() -> Hitlist {
     let mut list = Hitlist::new();
     list.add(Arc::new(shapes::BvhNode::fnew(&mut ground, 0.0, 1.0)));
 
-    let light = Arc::new(DiffuseLight::cnew(Color::new(7.0, 7.0, 7.0)));
-    list.add(Arc::new(shapes::XzRect::new(
-        123.0,
-        423.0,
-        147.0,
-        412.0,
-        554.0,
-        light.clone(),
-    )));
+    let light = Arc::new(DiffuseLight::snew(
+        Arc::new(texture::SolidColor::new(Color::new(7.0, 7.0, 7.0))),
+        1.0,
+        false,
+    ));
+    list.add(Arc::new(shapes::FlipFace::new(Arc::new(
+        shapes::XzRect::new(123.0, 423.0, 147.0, 412.0, 554.0, light.clone()),
+    ))));
 
     let ct1 = Vec3::new(400.0, 400.0, 200.0);
     let ct2 = ct1.clone() + Vec3::new(30.0, 0.0, 0.0);
@@ -383,11 +964,9 @@ pub fn final_scene() -> Hitlist {
         70.0,
         Arc::new(Dielectric::new(1.5)),
     ));
-    list.add(boundary.clone());
-    list.add(Arc::new(shapes::ConstantMedium::cnew(
-        boundary.clone(),
-        0.2,
-        Color::new(0.2, 0.4, 0.9),
+    list.add(Arc::new(medium::MediumInterface::new(
+        boundary,
+        Arc::new(medium::Homogeneous::cnew(0.2, Color::new(0.2, 0.4, 0.9))),
     )));
     boundary = Arc::new(Sphere::new(
         Vec3::zero(),
@@ -485,6 +1064,87 @@ pub fn moon() -> Hitlist {
     list
 }
 
+pub fn material_spheres() -> Hitlist {
+    let mut list = Hitlist::new();
+
+    let c1 = Color::new(0.2, 0.3, 0.1);
+    let c2 = Color::new(0.9, 0.9, 0.9);
+    let mat_g = Arc::new(Lamber::new(Arc::new(texture::CheckerTexture::cnew(c1, c2))));
+    list.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        mat_g,
+    )));
+
+    // back row: principled, middle row: microfacet conductors and frosted glass,
+    // front row: absorbing glass, layered materials and dispersive diamond
+    let plastic = Principled::cnew(Color::new(0.8, 0.1, 0.1), 0.0, 0.3).with_clearcoat(1.0);
+    let velvet = Principled::cnew(Color::new(0.2, 0.1, 0.5), 0.0, 0.9).with_sheen(1.0);
+    let marble = Principled::cnew(Color::new(0.9, 0.9, 0.9), 0.0, 0.2)
+        .with_roughness(Arc::new(texture::NoiseTexture::new(4.0)));
+    let glass = Principled::cnew(Color::new(0.8, 1.0, 0.9), 0.0, 0.05).with_transmission(1.0, 1.5);
+    let back: Vec<Arc<Material>> = vec![
+        Arc::new(plastic),
+        Arc::new(velvet),
+        Arc::new(Principled::cnew(Color::new(0.9, 0.6, 0.2), 1.0, 0.4)),
+        Arc::new(marble),
+        Arc::new(glass),
+    ];
+    let front: Vec<Arc<Material>> = vec![
+        Arc::new(RoughConductor::gold(0.2)),
+        Arc::new(RoughConductor::copper(0.5)),
+        Arc::new(RoughConductor::aluminium(0.05)),
+        Arc::new(RoughDielectric::cnew(1.5, 0.3)),
+        Arc::new(RoughDielectric::cnew(1.5, 0.05)),
+    ];
+    for (i, mat) in back.iter().enumerate() {
+        let x = -5.0 + 2.5 * i as f64;
+        list.add(Arc::new(Sphere::new(
+            Vec3::new(x, 1.0, -1.5),
+            1.0,
+            mat.clone(),
+        )));
+    }
+    let wood = Arc::new(Lamber::cnew(Color::new(0.45, 0.25, 0.1)));
+    let dirt = Arc::new(Lamber::cnew(Color::new(0.25, 0.2, 0.15)));
+    // tinted glass, absorbed inside the boundary rather than by the surface
+    list.add(Arc::new(medium::MediumInterface::new(
+        Arc::new(Sphere::new(
+            Vec3::new(-3.75, 0.8, 4.0),
+            0.8,
+            Arc::new(Dielectric::new(1.5)),
+        )),
+        Arc::new(medium::Absorbing::new(Color::new(0.6, 0.15, 0.05))),
+    )));
+    let layered: Vec<Arc<Material>> = vec![
+        Arc::new(Coated::cnew(wood, 1.5, 0.0)),
+        Arc::new(MixMaterial::new(
+            Arc::new(RoughConductor::aluminium(0.1)),
+            dirt,
+            Arc::new(texture::NoiseTexture::new(2.0)),
+        )),
+        Arc::new(Dielectric::inew(Ior::diamond(), Color::zero())),
+    ];
+    for (i, mat) in layered.iter().enumerate() {
+        let x = -1.25 + 2.5 * i as f64;
+        list.add(Arc::new(Sphere::new(
+            Vec3::new(x, 0.8, 4.0),
+            0.8,
+            mat.clone(),
+        )));
+    }
+    for (i, mat) in front.iter().enumerate() {
+        let x = -5.0 + 2.5 * i as f64;
+        list.add(Arc::new(Sphere::new(
+            Vec3::new(x, 1.0, 1.5),
+            1.0,
+            mat.clone(),
+        )));
+    }
+
+    list
+}
+
 pub fn randc() -> Color {
     let color1 = Color::new(0.714, 0.216, 0.027);
     let color2 = Color::new(0.2, 0.561, 0.890);
@@ -513,19 +1173,20 @@ pub fn idiy() -> Hitlist {
 
     let mat_left = Arc::new(Lamber::cnew(Color::new(0.48, 0.83, 0.53)));
     let mat_right = Arc::new(Lamber::new(Arc::new(texture::NoiseTexture::new(0.2))));
-    let arc_l = Arc::new(shapes::YzRect::new(
-        0.0, 1000.0, -1000.0, 1000.0, 0.0, mat_left,
+    // side walls tilted 5 degrees about their middle
+    let up = Vec3::new(0.0, 1000.0, 0.0);
+    let (s, c) = tools::dtr(5.0).sin_cos();
+    let arc_l = Arc::new(Quad::new(
+        Vec3::new(-1000.0 * s, 0.0, -1000.0 * c - 250.0),
+        up,
+        Vec3::new(2000.0 * s, 0.0, 2000.0 * c),
+        mat_left,
     ));
-    let arc_l = Arc::new(shapes::Translate::new(
-        Arc::new(shapes::RotateY::new(arc_l, 5.0)),
-        Vec3::new(0.0, 0.0, -250.0),
-    ));
-    let arc_r = Arc::new(shapes::YzRect::new(
-        0.0, 1000.0, -1000.0, 1000.0, 0.0, mat_right,
-    ));
-    let arc_r = Arc::new(shapes::Translate::new(
-        Arc::new(shapes::RotateY::new(arc_r, -5.0)),
-        Vec3::new(0.0, 0.0, 250.0),
+    let arc_r = Arc::new(Quad::new(
+        Vec3::new(1000.0 * s, 0.0, -1000.0 * c + 250.0),
+        up,
+        Vec3::new(-2000.0 * s, 0.0, 2000.0 * c),
+        mat_right,
     ));
     list.add(arc_l);
     list.add(arc_r);
@@ -644,8 +1305,79 @@ pub fn idiy() -> Hitlist {
     list
 }
 
+pub fn bench_cornell_box() {
+    let i_wid: i32 = 200;
+    let i_hit: i32 = 200;
+    const SAMPLES: i32 = 16;
+    const MAXDEEP: i32 = 50;
+
+    let mut list = cornell_box();
+    let bvh = shapes::BvhNode::fnew(&mut list, 0.0, 1.0);
+    let backgound = Color::zero();
+    let cam: Camera = Camera::new(
+        Vec3::new(278.0, 278.0, -800.0),
+        Vec3::new(278.0, 278.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        40.0,
+        1.0,
+        0.0,
+        10.0,
+        0.0,
+        1.0,
+    );
+
+    // returns seconds spent and the mean radiance over the image
+    let render = |estimator: &dyn Fn(Ray) -> Color| -> (f64, Color) {
+        let start = Instant::now();
+        let mut sum: Color = Color::zero();
+        for y in 0..i_hit {
+            for x in 0..i_wid {
+                for _ in 0..SAMPLES {
+                    let u: f64 = (x as f64 + randf(0.0, 1.0)) / ((i_wid - 1) as f64);
+                    let v: f64 = (y as f64 + randf(0.0, 1.0)) / ((i_hit - 1) as f64);
+                    sum += estimator(cam.get_ray(u, v));
+                }
+            }
+        }
+        let n = (i_wid * i_hit * SAMPLES) as f64;
+        (start.elapsed().as_secs_f64(), sum / n)
+    };
+
+    let recursive = |r: Ray| integrator::ray_color_recursive(r, &backgound, &bvh, MAXDEEP);
+    let (t_rec, m_rec) = render(&recursive);
+    let (t_it, m_it) = render(&|r| Integrator::new(MAXDEEP, MAXDEEP).li(r, &backgound, &bvh));
+    let (t_rr, m_rr) = render(&|r| Integrator::new(MAXDEEP, 3).li(r, &backgound, &bvh));
+
+    println!(
+        "cornell_box {}x{} @ {} spp, depth {}",
+        i_wid, i_hit, SAMPLES, MAXDEEP
+    );
+    println!("recursive:           {:.3}s mean {:?}", t_rec, m_rec);
+    println!(
+        "iterative:           {:.3}s mean {:?} ({:.2}x)",
+        t_it,
+        m_it,
+        t_rec / t_it
+    );
+    println!(
+        "iterative+roulette:  {:.3}s mean {:?} ({:.2}x)",
+        t_rr,
+        m_rr,
+        t_rec / t_rr
+    );
+}
+
 fn main() {
     // let mut file = File::create("image.ppm").unwrap();
+    let is_bench = match std::env::var("BENCH") {
+        Ok(x) => x == "true",
+        Err(_) => false,
+    };
+    if is_bench {
+        bench_cornell_box();
+        return;
+    }
+
     let is_ci = match std::env::var("CI") {
         Ok(x) => x == "true",
         Err(_) => false,
@@ -663,6 +1395,8 @@ fn main() {
     let mut i_hit: i32 = (i_wid as f64 / as_ratio) as i32;
     const SAMPLES: i32 = 3000; //500
     const MAXDEEP: i32 = 50; //50
+    const RRDEEP: i32 = 3;
+    const SPECTRAL: bool = false;
 
     let mut list = Hitlist::new();
 
@@ -672,13 +1406,15 @@ fn main() {
     let mut vfov = 40.0;
     let mut aperture = 0.0;
     let mut dist_to_focus = 10.0;
-    let mut backgound = Color::zero();
+    let mut backgound: Arc<Background> = Arc::new(Color::zero());
+    let mut lights: Vec<Arc<Light>> = Vec::new();
+    let mut animation: Option<Animation> = None;
 
     const TAC: i32 = 8;
     match TAC {
         0 => {
             list = random_scene();
-            backgound = Color::new(0.70, 0.80, 1.00);
+            backgound = Arc::new(Color::new(0.70, 0.80, 1.00));
             lookfrom = Vec3::new(13.0, 2.0, 3.0);
             lookat = Vec3::new(0.0, 0.0, 0.0);
             vfov = 20.0;
@@ -686,7 +1422,7 @@ fn main() {
         }
         1 => {
             list = two_sphere();
-            backgound = Color::new(0.70, 0.80, 1.00);
+            backgound = Arc::new(Color::new(0.70, 0.80, 1.00));
             lookfrom = Vec3::new(13.0, 2.0, 3.0);
             lookat = Vec3::new(0.0, 0.0, 0.0);
             vfov = 20.0;
@@ -694,7 +1430,7 @@ fn main() {
         }
         2 => {
             list = two_perlin();
-            backgound = Color::new(0.70, 0.80, 1.00);
+            backgound = Arc::new(Color::new(0.70, 0.80, 1.00));
             lookfrom = Vec3::new(13.0, 2.0, 3.0);
             lookat = Vec3::new(0.0, 0.0, 0.0);
             vfov = 20.0;
@@ -702,7 +1438,7 @@ fn main() {
         }
         3 => {
             list = earth();
-            backgound = Color::new(0.70, 0.80, 1.00);
+            backgound = Arc::new(Color::new(0.70, 0.80, 1.00));
             lookfrom = Vec3::new(13.0, 2.0, 3.0);
             lookat = Vec3::new(0.0, 0.0, 0.0);
             vfov = 20.0;
@@ -710,7 +1446,7 @@ fn main() {
         }
         4 => {
             list = simple_light();
-            backgound = Color::zero();
+            backgound = Arc::new(Color::zero());
             lookfrom = Vec3::new(26.0, 3.0, 6.0);
             lookat = Vec3::new(0.0, 2.0, 0.0);
             vfov = 20.0;
@@ -722,7 +1458,7 @@ fn main() {
             i_hit = 600;
 
             list = cornell_box();
-            backgound = Color::zero();
+            backgound = Arc::new(Color::zero());
             lookfrom = Vec3::new(278.0, 278.0, -800.0);
             lookat = Vec3::new(278.0, 278.0, 0.0);
             vfov = 40.0;
@@ -733,7 +1469,7 @@ fn main() {
             as_ratio = 1.0;
             i_wid = 800;
             i_hit = 800;
-            backgound = Color::zero();
+            backgound = Arc::new(Color::zero());
             lookfrom = Vec3::new(478.0, 278.0, -600.0);
             lookat = Vec3::new(278.0, 278.0, 0.0);
             vfov = 40.0;
@@ -745,7 +1481,7 @@ fn main() {
             as_ratio = 1.0;
 
             list = moon();
-            backgound = Color::zero();
+            backgound = Arc::new(Color::zero());
             lookfrom = Vec3::new(60.0, 2.0, 0.0);
             lookat = Vec3::new(0.0, 0.0, 0.0);
             vfov = 20.0;
@@ -756,15 +1492,225 @@ fn main() {
             as_ratio = 1.0;
             i_hit = 800;
             i_wid = 800;
-            backgound = Color::zero();
+            backgound = Arc::new(Color::zero());
             lookfrom = Vec3::new(1100.0, 190.0, 100.0);
             lookat = Vec3::new(700.0, 200.0, 50.0);
             vfov = 40.0;
             aperture = 0.0;
         }
+        9 => {
+            list = material_spheres();
+            backgound = Arc::new(Color::new(0.70, 0.80, 1.00));
+            lookfrom = Vec3::new(0.0, 3.0, 10.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 40.0;
+            aperture = 0.0;
+        }
+        10 => {
+            as_ratio = 1.0;
+            i_wid = 600;
+            i_hit = 600;
+
+            list = cornell_smoke();
+            backgound = Arc::new(Color::zero());
+            lookfrom = Vec3::new(278.0, 278.0, -800.0);
+            lookat = Vec3::new(278.0, 278.0, 0.0);
+            vfov = 40.0;
+            aperture = 0.0;
+        }
+        11 => {
+            as_ratio = 1.0;
+            i_wid = 600;
+            i_hit = 600;
+
+            list = cornell_fog();
+            backgound = Arc::new(Color::zero());
+            lookfrom = Vec3::new(278.0, 278.0, -800.0);
+            lookat = Vec3::new(278.0, 278.0, 0.0);
+            vfov = 40.0;
+            aperture = 0.0;
+        }
+        12 => {
+            as_ratio = 1.0;
+            i_wid = 600;
+            i_hit = 600;
+
+            list = cornell_wax();
+            backgound = Arc::new(Color::zero());
+            lookfrom = Vec3::new(278.0, 278.0, -800.0);
+            lookat = Vec3::new(278.0, 278.0, 0.0);
+            vfov = 40.0;
+            aperture = 0.0;
+        }
+        13 => {
+            list = material_spheres();
+            let path = Path::new("envmap.hdr");
+            backgound = match background::EnvMap::new(&path, 0.0, 1.0) {
+                Ok(env) => Arc::new(env),
+                Err(e) => {
+                    // still render the spheres, just without the lighting the map gives
+                    eprintln!("{}, using a plain sky instead", e);
+                    Arc::new(Color::new(0.70, 0.80, 1.00))
+                }
+            };
+            lookfrom = Vec3::new(0.0, 3.0, 10.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 40.0;
+            aperture = 0.0;
+        }
+        14 => {
+            list = random_scene();
+            // late afternoon sun from behind the camera's left shoulder
+            backgound = Arc::new(background::Sky::new(Vec3::new(1.0, 0.5, -1.0), 3.0, 1.0));
+            lookfrom = Vec3::new(13.0, 2.0, 3.0);
+            lookat = Vec3::new(0.0, 0.0, 0.0);
+            vfov = 20.0;
+            aperture = 0.1;
+        }
+        15 => {
+            let (l, ls) = delta_lights();
+            list = l;
+            lights = ls;
+            backgound = Arc::new(Color::new(0.02, 0.02, 0.03));
+            lookfrom = Vec3::new(0.0, 4.0, 12.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 35.0;
+            aperture = 0.0;
+        }
+        16 => {
+            let (l, ls) = ies_wall();
+            list = l;
+            lights = ls;
+            backgound = Arc::new(Color::zero());
+            lookfrom = Vec3::new(0.0, 2.5, 12.0);
+            lookat = Vec3::new(0.0, 2.5, 0.0);
+            vfov = 45.0;
+            aperture = 0.0;
+        }
+        17 => {
+            as_ratio = 1.0;
+            i_wid = 600;
+            i_hit = 600;
+
+            list = cornell_motion();
+            backgound = Arc::new(Color::zero());
+            lookfrom = Vec3::new(278.0, 278.0, -800.0);
+            lookat = Vec3::new(278.0, 278.0, 0.0);
+            vfov = 40.0;
+            aperture = 0.0;
+        }
+        18 => {
+            list = material_spheres();
+            backgound = Arc::new(Color::new(0.70, 0.80, 1.00));
+            let path = CameraPath::orbit(Vec3::new(0.0, 1.0, 0.0), 10.0, 2.0, 40.0, 2.0);
+            animation = Some(Animation::new(path, 48, 24.0, true));
+        }
+        19 => {
+            as_ratio = 1.0;
+            i_wid = 600;
+            i_hit = 600;
+
+            list = cornell_motion();
+            backgound = Arc::new(Color::zero());
+            let at = Vec3::new(278.0, 278.0, 0.0);
+            let path = CameraPath::new(
+                vec![
+                    CameraKey::new(0.0, Vec3::new(278.0, 278.0, -800.0), at, 40.0, 0.0, 800.0),
+                    CameraKey::new(1.0, Vec3::new(230.0, 300.0, -650.0), at, 36.0, 0.0, 650.0),
+                ],
+                Vec3::new(0.0, 1.0, 0.0),
+            );
+            animation = Some(Animation::new(path, 24, 24.0, false));
+        }
+        20 => {
+            list = quadrics();
+            backgound = Arc::new(Color::new(0.70, 0.80, 1.00));
+            lookfrom = Vec3::new(0.0, 4.0, 11.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 40.0;
+            aperture = 0.0;
+        }
+        21 => {
+            as_ratio = 1.0;
+            i_wid = 600;
+            i_hit = 600;
+
+            let (l, ls) = cornell_area();
+            list = l;
+            lights = ls;
+            backgound = Arc::new(Color::zero());
+            lookfrom = Vec3::new(278.0, 278.0, -800.0);
+            lookat = Vec3::new(278.0, 278.0, 0.0);
+            vfov = 40.0;
+            aperture = 0.0;
+        }
+        22 => {
+            list = csg_shapes();
+            backgound = Arc::new(Color::new(0.70, 0.80, 1.00));
+            lookfrom = Vec3::new(4.0, 5.0, 10.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 40.0;
+            aperture = 0.0;
+        }
+        23 => {
+            list = sdf_shapes();
+            backgound = Arc::new(Color::new(0.70, 0.80, 1.00));
+            lookfrom = Vec3::new(2.0, 4.0, 10.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 40.0;
+            aperture = 0.0;
+        }
+        24 => {
+            list = terrain();
+            backgound = Arc::new(Color::new(0.70, 0.80, 1.00));
+            lookfrom = Vec3::new(0.0, 12.0, 26.0);
+            lookat = Vec3::new(0.0, 2.0, 0.0);
+            vfov = 40.0;
+            aperture = 0.0;
+        }
+        25 => {
+            list = fur_and_grass();
+            backgound = Arc::new(Color::new(0.70, 0.80, 1.00));
+            lookfrom = Vec3::new(0.0, 1.2, 4.0);
+            lookat = Vec3::new(0.0, 0.6, 0.0);
+            vfov = 35.0;
+            aperture = 0.0;
+        }
         _ => {}
     }
 
+    let mut integrator = Integrator::new(MAXDEEP, RRDEEP);
+    integrator.spectral = SPECTRAL;
+    integrator.lights = lights;
+    let pool = ThreadPool::new(n_workers);
+
+    if let Some(anim) = animation {
+        // a static scene is bounded once over the whole sequence, otherwise
+        // each frame gets a tree fitted to its own shutter interval
+        let (t0, t1) = anim.span();
+        let mut bvh = shapes::BvhNode::fnew(&mut list, t0, t1);
+        for frame in 0..anim.frames {
+            let (tm0, tm1) = anim.shutter(frame);
+            if !anim.static_scene {
+                bvh = shapes::BvhNode::fnew(&mut list, tm0, tm1);
+            }
+            let cam: Arc<Viewer> = Arc::new(anim.camera(frame, as_ratio));
+            println!("frame {}/{}", frame + 1, anim.frames);
+            let img = render(
+                &pool,
+                cam,
+                &bvh,
+                &backgound,
+                &integrator,
+                (i_wid, i_hit),
+                SAMPLES,
+                n_jobs,
+            );
+            img.save(Animation::path(frame)).unwrap();
+        }
+        return;
+    }
+
     let cam: Camera = Camera::new(
         lookfrom.clone(),
         lookat.clone(),
@@ -776,21 +1722,46 @@ fn main() {
         0.0,
         1.0,
     );
+    let bvh = shapes::BvhNode::fnew(&mut list, 0.0, 1.0);
+    let img = render(
+        &pool,
+        Arc::new(cam),
+        &bvh,
+        &backgound,
+        &integrator,
+        (i_wid, i_hit),
+        SAMPLES,
+        n_jobs,
+    );
+    img.save("output/test.png").unwrap();
+}
 
+// one image, its rows split into n_jobs bands rendered on the pool
+pub fn render(
+    pool: &ThreadPool,
+    cam: Arc<Viewer>,
+    bvh: &shapes::BvhNode,
+    backgound: &Arc<Background>,
+    integrator: &Integrator,
+    size: (i32, i32),
+    samples: i32,
+    n_jobs: usize,
+) -> RgbImage {
+    let (i_wid, i_hit) = size;
     let (tx, rx) = channel();
-    let pool = ThreadPool::new(n_workers);
 
     let bar = ProgressBar::new(n_jobs as u64);
 
     let world = Arc::new(World::new(i_hit as u32));
 
-    let bvh = shapes::BvhNode::fnew(&mut list, 0.0, 1.0);
-
     // file.write(format!("P3\n{} {}\n255\n", i_wid, i_hit).as_bytes());
     for i in 0..n_jobs {
         let tx = tx.clone();
         let world_ptr = world.clone();
         let t_list = bvh.clone();
+        let backgound = backgound.clone();
+        let integrator = integrator.clone();
+        let cam = cam.clone();
         pool.execute(move || {
             let row_begin = i_hit as usize * i / n_jobs;
             let row_end = i_hit as usize * (i + 1) / n_jobs;
@@ -802,15 +1773,15 @@ fn main() {
                     let y = (i_hit as usize - 1 - y) as u32;
                     let mut color: Color = Color::new(0.0, 0.0, 0.0);
                     let mut s: i32 = 0;
-                    while s < SAMPLES {
+                    while s < samples {
                         let u: f64 = (x as f64 + randf(0.0, 1.0)) / ((i_wid - 1) as f64);
                         let v: f64 = (y as f64 + randf(0.0, 1.0)) / ((i_hit - 1) as f64);
                         let r: Ray = cam.get_ray(u, v);
-                        color += ray_color(r, &backgound, &t_list, MAXDEEP);
+                        color += integrator.li(r, &*backgound, &t_list);
                         s += 1;
                     }
                     let pixel = img.get_pixel_mut(x as u32, img_y as u32);
-                    let otc: Color = color::out_color(color.clone(), SAMPLES);
+                    let otc: Color = color::out_color(color.clone(), samples);
                     *pixel = image::Rgb([otc.x() as u8, otc.y() as u8, otc.z() as u8]);
                     // color::write_color(&mut file, color, SAMPLES);
                 }
@@ -833,6 +1804,6 @@ fn main() {
         bar.inc(1);
     }
 
-    img.save("output/test.png").unwrap();
     bar.finish();
+    img
 }
@@ -1,6 +1,11 @@
+use crate::color;
 use crate::color::Color;
+use crate::ies::IesProfile;
+use crate::microfacet;
 use crate::ray::Ray;
 use crate::shapes::Hitrec;
+use crate::spectrum;
+use crate::spectrum::Ior;
 use crate::texture;
 use crate::texture::Texture;
 use crate::tools;
@@ -11,10 +16,32 @@ use std::fmt::Debug;
 use std::sync::Arc;
 
 pub trait Material: Debug + Send + Sync {
-    fn scatter(&self, r_in: Ray, rec: Hitrec, att: &mut Color, scat: &mut Ray) -> bool;
-    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Color {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool;
+    fn emitted(&self, r_in: &Ray, rec: &Hitrec) -> Color {
         Color::zero()
     }
+    // true when the scattered direction depends on the ray's wavelength
+    fn dispersive(&self) -> bool {
+        false
+    }
+    // bsdf times the cosine towards the unit direction wi, for light sampling
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        Color::zero()
+    }
+    // solid angle density with which scatter picks wi
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        0.0
+    }
+    // eval and pdf cover everything scatter can do at rec, so sampled lights
+    // can be weighted against scattering without losing energy
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        false
+    }
+    // scatter only dims the ray and lets it go on straight, a null collision
+    // that takes no bounce of the path's depth
+    fn null_collision(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -27,7 +54,7 @@ impl Neg {
 }
 
 impl Material for Neg {
-    fn scatter(&self, r_in: Ray, rec: Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
         false
     }
 }
@@ -49,12 +76,26 @@ impl Lamber {
 }
 
 impl Material for Lamber {
-    fn scatter(&self, r_in: Ray, rec: Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
         let scat_dir: Vec3 = rec.nf() + vec3::rand_uint_vec();
-        scat.copy(Ray::new(rec.p(), scat_dir.clone(), r_in.time()));
+        scat.copy(Ray::wnew(
+            rec.p(),
+            scat_dir.clone(),
+            r_in.time(),
+            r_in.lambda,
+        ));
         att.copy(self.lbc.value(rec.u, rec.v, &rec.p));
         true
     }
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        self.lbc.value(rec.u, rec.v, &rec.p) * self.pdf(r_in, rec, wi)
+    }
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        (*wi * rec.nf()).max(0.0) / tools::PI
+    }
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        true
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -76,16 +117,43 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: Ray, rec: Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
         let rft: Vec3 = Vec3::reflect((r_in.diraction()).unit(), rec.nf());
-        scat.copy(Ray::new(
+        scat.copy(Ray::wnew(
             rec.p(),
             rft.clone() + vec3::rand_in_unit_sphere() * self.fuz(),
             r_in.time(),
+            r_in.lambda,
         ));
         att.copy(self.color());
         scat.diraction() * rec.nf() > 0.0
     }
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        self.color() * self.pdf(r_in, rec, wi)
+    }
+    // scatter aims at a point uniform in the fuzz ball around the mirror
+    // direction, so wi is as likely as the ball's volume along it
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        let d: Vec3 = wi.unit();
+        if self.fuzz <= 0.0 || d * rec.nf() <= 0.0 {
+            return 0.0;
+        }
+        let rft: Vec3 = Vec3::reflect((r_in.diraction()).unit(), rec.nf());
+        let c: f64 = d * rft;
+        let disc: f64 = c * c - 1.0 + self.fuzz * self.fuzz;
+        if disc < 0.0 {
+            return 0.0;
+        }
+        let far: f64 = c + disc.sqrt();
+        let near: f64 = (c - disc.sqrt()).max(0.0);
+        if far <= 0.0 {
+            return 0.0;
+        }
+        (far.powi(3) - near.powi(3)) / (4.0 * tools::PI * self.fuzz.powi(3))
+    }
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        self.fuzz > 0.0
+    }
 }
 
 pub fn schlick(cosine: f64, ref_idx: f64) -> f64 {
@@ -97,11 +165,30 @@ pub fn schlick(cosine: f64, ref_idx: f64) -> f64 {
 #[derive(Debug, Clone)]
 pub struct Dielectric {
     pub ref_idx: f64,
+    pub ior: Ior,
+    // per unit of distance travelled inside. applied when a ray leaves, over
+    // the whole segment that led to the exit, which assumes the ray was inside
+    // this same object all along: right for a lone closed shape seen from
+    // outside, wrong for dielectrics nested in one another or a camera that
+    // starts inside. put a MediumInterface with an Absorbing medium behind a
+    // clear Dielectric for those
+    pub absorption: Color,
 }
 
 impl Dielectric {
     pub fn new(ref_idx: f64) -> Self {
-        Self { ref_idx }
+        Self {
+            ref_idx,
+            ior: Ior::Constant(ref_idx),
+            absorption: Color::zero(),
+        }
+    }
+    pub fn inew(ior: Ior, absorption: Color) -> Self {
+        Self {
+            ref_idx: ior.at(589.3),
+            ior,
+            absorption,
+        }
     }
     pub fn rdx(&self) -> f64 {
         self.ref_idx.clone()
@@ -109,55 +196,906 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: Ray, rec: Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
         att.copy(Color::new(1.0, 1.0, 1.0));
-        let rate: f64 = if rec.front_face {
-            1.0 / self.rdx()
-        } else {
-            self.rdx()
-        };
+        if !rec.front_face {
+            // leaving the object, beer-lambert over the segment just travelled
+            let dis: f64 = rec.t * r_in.diraction().length();
+            let tr: Color = -self.absorption * dis;
+            att.copy(Color::new(tr.x().exp(), tr.y().exp(), tr.z().exp()));
+        }
+
+        // a dispersive path sticks to one wavelength from here on
+        let mut lambda: f64 = r_in.lambda;
+        let mut idx: f64 = self.rdx();
+        if self.ior.dispersive() {
+            if lambda <= 0.0 {
+                lambda = spectrum::sample_wavelength();
+                att.copy(Color::elemul(*att, spectrum::wavelength_weight(lambda)));
+            }
+            idx = self.ior.at(lambda);
+        }
+
+        let rate: f64 = if rec.front_face { 1.0 / idx } else { idx };
         let uint_dir: Vec3 = r_in.diraction().unit();
         let tem_cos: f64 = -uint_dir.clone() * rec.nf();
         let cos_theta: f64 = if tem_cos < 1.0 { tem_cos } else { 1.0 };
         let sin_theta: f64 = (1.0 - cos_theta * cos_theta).sqrt();
         if rate * sin_theta > 1.0 {
             let refec: Vec3 = Vec3::reflect(uint_dir.clone(), rec.nf());
-            scat.copy(Ray::new(rec.p(), refec.clone(), r_in.time()));
+            scat.copy(Ray::wnew(rec.p(), refec.clone(), r_in.time(), lambda));
         } else {
             let prob: f64 = schlick(cos_theta, rate);
             if tools::randf(0.0, 1.0) < prob {
                 let refec: Vec3 = Vec3::reflect(uint_dir.clone(), rec.nf());
-                scat.copy(Ray::new(rec.p(), refec.clone(), r_in.time()));
+                scat.copy(Ray::wnew(rec.p(), refec.clone(), r_in.time(), lambda));
             } else {
                 let refac: Vec3 = Vec3::refract(uint_dir.clone(), rec.nf(), rate);
-                scat.copy(Ray::new(rec.p(), refac.clone(), r_in.time()));
+                scat.copy(Ray::wnew(rec.p(), refac.clone(), r_in.time(), lambda));
             }
         }
         true
     }
+    fn dispersive(&self) -> bool {
+        self.ior.dispersive()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RoughConductor {
+    pub eta: Color,
+    pub k: Color,
+    pub roughness: Arc<Texture>,
+}
+
+impl RoughConductor {
+    pub fn new(eta: Color, k: Color, roughness: Arc<Texture>) -> Self {
+        Self { eta, k, roughness }
+    }
+    pub fn cnew(eta: Color, k: Color, roughness: f64) -> Self {
+        Self {
+            eta,
+            k,
+            roughness: Arc::new(texture::SolidColor::new(Color::ones() * roughness)),
+        }
+    }
+    pub fn gold(roughness: f64) -> Self {
+        Self::cnew(
+            Color::new(0.143, 0.374, 1.442),
+            Color::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+    pub fn copper(roughness: f64) -> Self {
+        Self::cnew(
+            Color::new(0.200, 0.924, 1.102),
+            Color::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::cnew(
+            Color::new(1.657, 0.880, 0.521),
+            Color::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+}
+
+impl Material for RoughConductor {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+        let alpha: f64 = microfacet::alpha(self.roughness.value(rec.u, rec.v, &rec.p).x());
+        let wo: Vec3 = -r_in.diraction().unit();
+        match microfacet::sample_reflection(wo, rec.nf(), alpha) {
+            Some((wi, weight, cos_oh)) => {
+                att.copy(microfacet::fresnel_conductor(cos_oh, self.eta, self.k) * weight);
+                scat.copy(Ray::wnew(rec.p(), wi, r_in.time(), r_in.lambda));
+                true
+            }
+            None => false,
+        }
+    }
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        let alpha: f64 = microfacet::alpha(self.roughness.value(rec.u, rec.v, &rec.p).x());
+        let wo: Vec3 = -r_in.diraction().unit();
+        match microfacet::eval_reflection(wo, *wi, rec.nf(), alpha) {
+            Some((f, _, cos_oh)) => microfacet::fresnel_conductor(cos_oh, self.eta, self.k) * f,
+            None => Color::zero(),
+        }
+    }
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        let alpha: f64 = microfacet::alpha(self.roughness.value(rec.u, rec.v, &rec.p).x());
+        let wo: Vec3 = -r_in.diraction().unit();
+        match microfacet::eval_reflection(wo, *wi, rec.nf(), alpha) {
+            Some((_, pdf, _)) => pdf,
+            None => 0.0,
+        }
+    }
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RoughDielectric {
+    pub ref_idx: f64,
+    pub roughness: Arc<Texture>,
+}
+
+impl RoughDielectric {
+    pub fn new(ref_idx: f64, roughness: Arc<Texture>) -> Self {
+        Self { ref_idx, roughness }
+    }
+    pub fn cnew(ref_idx: f64, roughness: f64) -> Self {
+        Self {
+            ref_idx,
+            roughness: Arc::new(texture::SolidColor::new(Color::ones() * roughness)),
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+        let alpha: f64 = microfacet::alpha(self.roughness.value(rec.u, rec.v, &rec.p).x());
+        let rate: f64 = if rec.front_face {
+            1.0 / self.ref_idx
+        } else {
+            self.ref_idx
+        };
+        let wo: Vec3 = -r_in.diraction().unit();
+        let n: Vec3 = rec.nf();
+        let cos_o: f64 = wo * n;
+        if cos_o <= 0.0 {
+            return false;
+        }
+        let h: Vec3 = vec3::Onb::new(n).local(microfacet::sample_ggx(alpha));
+        let cos_oh: f64 = wo * h;
+        if cos_oh <= 0.0 {
+            return false;
+        }
+
+        // pick reflection or refraction through the sampled microfacet by its fresnel term
+        let prob: f64 = microfacet::fresnel_dielectric(cos_oh, rate);
+        let reflected: bool = tools::randf(0.0, 1.0) < prob;
+        let wi: Vec3 = if reflected {
+            Vec3::reflect(-wo, h)
+        } else {
+            Vec3::refract(-wo, h, rate)
+        };
+        let cos_i: f64 = wi * n;
+        // a steep microfacet can send the ray to the wrong side of the surface
+        if (reflected && cos_i <= 0.0) || (!reflected && cos_i >= 0.0) {
+            return false;
+        }
+
+        let weight: f64 = microfacet::smith_g(cos_o, cos_i, alpha) * cos_oh / (cos_o * (h * n));
+        att.copy(Color::ones() * weight);
+        scat.copy(Ray::wnew(rec.p(), wi.unit(), r_in.time(), r_in.lambda));
+        true
+    }
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        match self.eval_pdf(r_in, rec, wi) {
+            Some((f, _)) => Color::ones() * f,
+            None => Color::zero(),
+        }
+    }
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        match self.eval_pdf(r_in, rec, wi) {
+            Some((_, pdf)) => pdf,
+            None => 0.0,
+        }
+    }
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        true
+    }
+}
+
+impl RoughDielectric {
+    // f * cos_i and the pdf of scatter picking wi, through the microfacet
+    // that reflects or refracts wo into wi. like scatter this leaves out the
+    // 1 / eta^2 radiance scaling on refraction
+    fn eval_pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Option<(f64, f64)> {
+        let alpha: f64 = microfacet::alpha(self.roughness.value(rec.u, rec.v, &rec.p).x());
+        let rate: f64 = if rec.front_face {
+            1.0 / self.ref_idx
+        } else {
+            self.ref_idx
+        };
+        let wo: Vec3 = -r_in.diraction().unit();
+        let wi: Vec3 = wi.unit();
+        let n: Vec3 = rec.nf();
+        let cos_o: f64 = wo * n;
+        let cos_i: f64 = wi * n;
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return None;
+        }
+        let reflected: bool = cos_i > 0.0;
+        let mut h: Vec3 = if reflected {
+            (wo + wi).unit()
+        } else {
+            (wo + wi / rate).unit()
+        };
+        if h * n < 0.0 {
+            h = -h;
+        }
+        let cos_oh: f64 = wo * h;
+        let cos_ih: f64 = wi * h;
+        if cos_oh <= 0.0 || (reflected && cos_ih <= 0.0) || (!reflected && cos_ih >= 0.0) {
+            return None;
+        }
+        let d: f64 = microfacet::ggx_d(h * n, alpha);
+        let g: f64 = microfacet::smith_g(cos_o, cos_i, alpha);
+        let fr: f64 = microfacet::fresnel_dielectric(cos_oh, rate);
+        // density of the half vector turned into one of wi
+        let dh_dwi: f64 = if reflected {
+            1.0 / (4.0 * cos_oh)
+        } else {
+            let denom: f64 = cos_oh + cos_ih / rate;
+            cos_ih.abs() / (rate * rate * denom * denom)
+        };
+        let prob: f64 = if reflected { fr } else { 1.0 - fr };
+        let pdf: f64 = prob * d * (h * n) * dh_dwi;
+        Some((pdf * g * cos_oh / (cos_o * (h * n)), pdf))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Principled {
+    pub base_color: Arc<Texture>,
+    pub metallic: Arc<Texture>,
+    pub roughness: Arc<Texture>,
+    pub specular: Arc<Texture>,
+    pub sheen: Arc<Texture>,
+    pub clearcoat: Arc<Texture>,
+    pub transmission: Arc<Texture>,
+    pub emission: Arc<Texture>,
+    pub ior: f64,
+}
+
+impl Principled {
+    pub fn new(
+        base_color: Arc<Texture>,
+        metallic: Arc<Texture>,
+        roughness: Arc<Texture>,
+        specular: Arc<Texture>,
+        sheen: Arc<Texture>,
+        clearcoat: Arc<Texture>,
+        transmission: Arc<Texture>,
+        emission: Arc<Texture>,
+        ior: f64,
+    ) -> Self {
+        Self {
+            base_color,
+            metallic,
+            roughness,
+            specular,
+            sheen,
+            clearcoat,
+            transmission,
+            emission,
+            ior,
+        }
+    }
+    // constant parameters with no sheen, clearcoat, transmission or emission;
+    // swap single fields for textures afterwards
+    pub fn cnew(base_color: Color, metallic: f64, roughness: f64) -> Self {
+        let scalar =
+            |x: f64| -> Arc<Texture> { Arc::new(texture::SolidColor::new(Color::ones() * x)) };
+        Self {
+            base_color: Arc::new(texture::SolidColor::new(base_color)),
+            metallic: scalar(metallic),
+            roughness: scalar(roughness),
+            specular: scalar(0.5),
+            sheen: scalar(0.0),
+            clearcoat: scalar(0.0),
+            transmission: scalar(0.0),
+            emission: Arc::new(texture::SolidColor::new(Color::zero())),
+            ior: 1.5,
+        }
+    }
+
+    // chained after cnew, e.g. Principled::cnew(red, 0.0, 0.3).with_clearcoat(1.0)
+    pub fn with_specular(mut self, specular: f64) -> Self {
+        self.specular = Arc::new(texture::SolidColor::new(Color::ones() * specular));
+        self
+    }
+    pub fn with_sheen(mut self, sheen: f64) -> Self {
+        self.sheen = Arc::new(texture::SolidColor::new(Color::ones() * sheen));
+        self
+    }
+    pub fn with_clearcoat(mut self, clearcoat: f64) -> Self {
+        self.clearcoat = Arc::new(texture::SolidColor::new(Color::ones() * clearcoat));
+        self
+    }
+    pub fn with_transmission(mut self, transmission: f64, ior: f64) -> Self {
+        self.transmission = Arc::new(texture::SolidColor::new(Color::ones() * transmission));
+        self.ior = ior;
+        self
+    }
+    pub fn with_emission(mut self, emission: Color) -> Self {
+        self.emission = Arc::new(texture::SolidColor::new(emission));
+        self
+    }
+    pub fn with_roughness(mut self, roughness: Arc<Texture>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+}
+
+// parameters of a Principled hit and the probability weights of its lobes.
+// the coat lets through what its fresnel does not reflect, and the diffuse
+// base only gets what the specular layer lets through, so nothing gains energy
+struct Lobes {
+    base: Color,
+    layer: f64,   // share left by the clearcoat towards wo
+    under: Color, // share left for the diffuse lobe by both layers above it
+    metallic: f64,
+    alpha: f64,
+    sheen: f64,
+    clearcoat: f64,
+    transmission: f64,
+    f0: Color,
+    w_diffuse: f64,
+    w_glossy: f64,
+    w_trans: f64,
+    w_coat: f64,
+    total: f64,
+}
+
+impl Principled {
+    fn lobes(&self, rec: &Hitrec, wo: Vec3) -> Lobes {
+        let (u, v, p) = (rec.u, rec.v, &rec.p);
+        let base: Color = self.base_color.value(u, v, p);
+        let metallic: f64 = tools::clamp(self.metallic.value(u, v, p).x(), 0.0, 1.0);
+        let specular: f64 = self.specular.value(u, v, p).x().max(0.0);
+        let transmission: f64 = tools::clamp(self.transmission.value(u, v, p).x(), 0.0, 1.0);
+        let clearcoat: f64 = tools::clamp(self.clearcoat.value(u, v, p).x(), 0.0, 1.0);
+
+        let f0: Color = Color::ones() * (0.08 * specular) * (1.0 - metallic) + base * metallic;
+        let cos_o: f64 = wo * rec.nf();
+        let coat_f: f64 =
+            0.25 * clearcoat * microfacet::schlick_color(cos_o, Color::ones() * 0.04).x();
+        let layer: f64 = 1.0 - coat_f;
+        let under: Color = (Color::ones() - microfacet::schlick_color(cos_o, f0)) * layer;
+
+        let mut w_diffuse: f64 =
+            (1.0 - metallic) * (1.0 - transmission) * color::luminance(Color::elemul(base, under));
+        let mut w_glossy: f64 = color::luminance(f0).max(0.05) * layer;
+        let w_trans: f64 = (1.0 - metallic) * transmission * layer;
+        let mut w_coat: f64 = 0.25 * clearcoat;
+        if !rec.front_face {
+            // only the transmissive lobe sees the inside of a surface
+            w_diffuse = 0.0;
+            w_glossy = 0.0;
+            w_coat = 0.0;
+        }
+        Lobes {
+            base,
+            layer,
+            under,
+            metallic,
+            alpha: microfacet::alpha(self.roughness.value(u, v, p).x()),
+            sheen: tools::clamp(self.sheen.value(u, v, p).x(), 0.0, 1.0),
+            clearcoat,
+            transmission,
+            f0,
+            w_diffuse,
+            w_glossy,
+            w_trans,
+            w_coat,
+            total: w_diffuse + w_glossy + w_trans + w_coat,
+        }
+    }
+
+    fn diffuse(l: &Lobes, wo: Vec3, wi: Vec3) -> Color {
+        let cos_d: f64 = tools::clamp(wi * wo, 0.0, 1.0);
+        // sheen brightens grazing light towards white rather than adding to it
+        let s: f64 = l.sheen * (1.0 - cos_d).powf(5.0);
+        let tint: Color = l.base * (1.0 - s) + Color::ones() * s;
+        Color::elemul(tint, l.under) * ((1.0 - l.metallic) * (1.0 - l.transmission))
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+        let wo: Vec3 = -r_in.diraction().unit();
+        let l: Lobes = self.lobes(rec, wo);
+        let n: Vec3 = rec.nf();
+        if l.total <= 0.0 || (!rec.front_face && l.w_trans <= 0.0) {
+            return false;
+        }
+
+        // one lobe is picked per scatter and its weight divided by the pick probability
+        let pick: f64 = tools::randf(0.0, l.total);
+        if pick < l.w_diffuse {
+            let mut dir: Vec3 = n + vec3::rand_uint_vec();
+            if dir.squared_length() < 1e-12 {
+                dir = n;
+            }
+            let lobe: Color = Self::diffuse(&l, wo, dir.unit());
+            att.copy(lobe * (l.total / l.w_diffuse));
+            scat.copy(Ray::wnew(rec.p(), dir, r_in.time(), r_in.lambda));
+            return true;
+        }
+        if pick < l.w_diffuse + l.w_glossy {
+            return match microfacet::sample_reflection(wo, n, l.alpha) {
+                Some((wi, weight, cos_oh)) => {
+                    let f: Color = microfacet::schlick_color(cos_oh, l.f0);
+                    att.copy(f * (l.layer * weight * l.total / l.w_glossy));
+                    scat.copy(Ray::wnew(rec.p(), wi, r_in.time(), r_in.lambda));
+                    true
+                }
+                None => false,
+            };
+        }
+        if pick < l.w_diffuse + l.w_glossy + l.w_trans {
+            let glass = RoughDielectric::new(self.ior, self.roughness.clone());
+            if !glass.scatter(r_in, rec, att, scat) {
+                return false;
+            }
+            // tint light on the way in, the way out stays clear
+            let tint: Color = if rec.front_face && scat.diraction() * n < 0.0 {
+                l.base
+            } else {
+                Color::ones()
+            };
+            let lobe: f64 = (1.0 - l.metallic) * l.transmission * l.layer;
+            att.copy(Color::elemul(*att, tint) * (lobe * l.total / l.w_trans));
+            return true;
+        }
+        match microfacet::sample_reflection(wo, n, 0.1) {
+            Some((wi, weight, cos_oh)) => {
+                let f: Color = microfacet::schlick_color(cos_oh, Color::ones() * 0.04);
+                att.copy(f * (0.25 * l.clearcoat * weight * l.total / l.w_coat));
+                scat.copy(Ray::wnew(rec.p(), wi, r_in.time(), r_in.lambda));
+                true
+            }
+            None => false,
+        }
+    }
+    fn emitted(&self, r_in: &Ray, rec: &Hitrec) -> Color {
+        self.emission.value(rec.u, rec.v, &rec.p)
+    }
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        let wo: Vec3 = -r_in.diraction().unit();
+        let l: Lobes = self.lobes(rec, wo);
+        let n: Vec3 = rec.nf();
+        let cos_i: f64 = *wi * n;
+        if !rec.front_face || cos_i <= 0.0 {
+            return Color::zero();
+        }
+        let mut f: Color = Self::diffuse(&l, wo, *wi) * (cos_i / tools::PI);
+        if let Some((spec, _, cos_oh)) = microfacet::eval_reflection(wo, *wi, n, l.alpha) {
+            f += microfacet::schlick_color(cos_oh, l.f0) * (l.layer * spec);
+        }
+        if l.clearcoat > 0.0 {
+            if let Some((spec, _, cos_oh)) = microfacet::eval_reflection(wo, *wi, n, 0.1) {
+                let coat: Color = microfacet::schlick_color(cos_oh, Color::ones() * 0.04);
+                f += coat * (0.25 * l.clearcoat * spec);
+            }
+        }
+        f
+    }
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        let wo: Vec3 = -r_in.diraction().unit();
+        let l: Lobes = self.lobes(rec, wo);
+        let n: Vec3 = rec.nf();
+        if l.total <= 0.0 {
+            return 0.0;
+        }
+        let mut pdf: f64 = l.w_diffuse * (*wi * n).max(0.0) / tools::PI;
+        if let Some((_, p, _)) = microfacet::eval_reflection(wo, *wi, n, l.alpha) {
+            pdf += l.w_glossy * p;
+        }
+        if let Some((_, p, _)) = microfacet::eval_reflection(wo, *wi, n, 0.1) {
+            pdf += l.w_coat * p;
+        }
+        pdf / l.total
+    }
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        // the glass lobe is left to scatter
+        rec.front_face && self.lobes(rec, rec.nf()).w_trans <= 0.0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MixMaterial {
+    pub a: Arc<Material>,
+    pub b: Arc<Material>,
+    pub weight: Arc<Texture>, // 0 picks a, 1 picks b
+}
+
+impl MixMaterial {
+    pub fn new(a: Arc<Material>, b: Arc<Material>, weight: Arc<Texture>) -> Self {
+        Self { a, b, weight }
+    }
+    pub fn cnew(a: Arc<Material>, b: Arc<Material>, weight: f64) -> Self {
+        Self {
+            a,
+            b,
+            weight: Arc::new(texture::SolidColor::new(Color::ones() * weight)),
+        }
+    }
+    fn weight(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        tools::clamp(self.weight.value(u, v, p).x(), 0.0, 1.0)
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+        if tools::randf(0.0, 1.0) < self.weight(rec.u, rec.v, &rec.p) {
+            self.b.scatter(r_in, rec, att, scat)
+        } else {
+            self.a.scatter(r_in, rec, att, scat)
+        }
+    }
+    fn emitted(&self, r_in: &Ray, rec: &Hitrec) -> Color {
+        let w: f64 = self.weight(rec.u, rec.v, &rec.p);
+        self.a.emitted(r_in, rec) * (1.0 - w) + self.b.emitted(r_in, rec) * w
+    }
+    fn dispersive(&self) -> bool {
+        self.a.dispersive() || self.b.dispersive()
+    }
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        let w: f64 = self.weight(rec.u, rec.v, &rec.p);
+        self.a.eval(r_in, rec, wi) * (1.0 - w) + self.b.eval(r_in, rec, wi) * w
+    }
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        let w: f64 = self.weight(rec.u, rec.v, &rec.p);
+        self.a.pdf(r_in, rec, wi) * (1.0 - w) + self.b.pdf(r_in, rec, wi) * w
+    }
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        self.a.evaluable(rec) && self.b.evaluable(rec)
+    }
+}
+
+// a thin clear dielectric layer on top of base, e.g. varnish
+#[derive(Debug, Clone)]
+pub struct Coated {
+    pub base: Arc<Material>,
+    pub ior: f64,
+    pub roughness: Arc<Texture>,
+}
+
+impl Coated {
+    pub fn new(base: Arc<Material>, ior: f64, roughness: Arc<Texture>) -> Self {
+        Self {
+            base,
+            ior,
+            roughness,
+        }
+    }
+    pub fn cnew(base: Arc<Material>, ior: f64, roughness: f64) -> Self {
+        Self {
+            base,
+            ior,
+            roughness: Arc::new(texture::SolidColor::new(Color::ones() * roughness)),
+        }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+        if !rec.front_face {
+            return self.base.scatter(r_in, rec, att, scat);
+        }
+        let wo: Vec3 = -r_in.diraction().unit();
+        let n: Vec3 = rec.nf();
+        let rate: f64 = 1.0 / self.ior;
+
+        // reflect off the coat with its fresnel probability, otherwise pass
+        // through it twice and let the base do the scattering
+        let f_in: f64 = microfacet::fresnel_dielectric(wo * n, rate);
+        if tools::randf(0.0, 1.0) < f_in {
+            let alpha: f64 = microfacet::alpha(self.roughness.value(rec.u, rec.v, &rec.p).x());
+            return match microfacet::sample_reflection(wo, n, alpha) {
+                Some((wi, weight, _)) => {
+                    att.copy(Color::ones() * weight);
+                    scat.copy(Ray::wnew(rec.p(), wi, r_in.time(), r_in.lambda));
+                    true
+                }
+                None => false,
+            };
+        }
+        if !self.base.scatter(r_in, rec, att, scat) {
+            return false;
+        }
+        let cos_i: f64 = scat.diraction().unit() * n;
+        if cos_i > 0.0 {
+            let f_out: f64 = microfacet::fresnel_dielectric(cos_i, rate);
+            att.copy(*att * (1.0 - f_out));
+        }
+        true
+    }
+    fn emitted(&self, r_in: &Ray, rec: &Hitrec) -> Color {
+        self.base.emitted(r_in, rec)
+    }
+    fn dispersive(&self) -> bool {
+        self.base.dispersive()
+    }
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        if !rec.front_face {
+            return self.base.eval(r_in, rec, wi);
+        }
+        let wo: Vec3 = -r_in.diraction().unit();
+        let n: Vec3 = rec.nf();
+        let rate: f64 = 1.0 / self.ior;
+        let f_in: f64 = microfacet::fresnel_dielectric(wo * n, rate);
+        let alpha: f64 = microfacet::alpha(self.roughness.value(rec.u, rec.v, &rec.p).x());
+        let mut f: Color = self.base.eval(r_in, rec, wi) * (1.0 - f_in);
+        let cos_i: f64 = *wi * n;
+        if cos_i > 0.0 {
+            f = f * (1.0 - microfacet::fresnel_dielectric(cos_i, rate));
+        }
+        if let Some((spec, _, _)) = microfacet::eval_reflection(wo, *wi, n, alpha) {
+            f += Color::ones() * (f_in * spec);
+        }
+        f
+    }
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        if !rec.front_face {
+            return self.base.pdf(r_in, rec, wi);
+        }
+        let wo: Vec3 = -r_in.diraction().unit();
+        let n: Vec3 = rec.nf();
+        let f_in: f64 = microfacet::fresnel_dielectric(wo * n, 1.0 / self.ior);
+        let alpha: f64 = microfacet::alpha(self.roughness.value(rec.u, rec.v, &rec.p).x());
+        let mut pdf: f64 = (1.0 - f_in) * self.base.pdf(r_in, rec, wi);
+        if let Some((_, p, _)) = microfacet::eval_reflection(wo, *wi, n, alpha) {
+            pdf += f_in * p;
+        }
+        pdf
+    }
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        self.base.evaluable(rec)
+    }
+}
+
+// a fibre seen as a thin dielectric cylinder along rec.tangent, after
+// marschner: light reflects off the cuticle (R), passes straight through (TT)
+// or bounces once inside (TRT), each lobe tilted by the cuticle scales.
+// the lobe is picked by its share of the energy and blurred by gaussians
+// that eval and pdf sum over, so lights can be sampled too.
+// rec.v runs across the fibre and gives the offset the ray hits it at
+#[derive(Debug, Clone)]
+pub struct Hair {
+    pub sigma_a: Color, // absorption per diameter travelled inside
+    pub beta: f64,      // longitudinal roughness in radians
+    pub alpha: f64,     // scale tilt in degrees
+    pub eta: f64,
+}
+
+impl Hair {
+    pub fn new(sigma_a: Color, beta: f64) -> Self {
+        Self {
+            sigma_a,
+            beta,
+            alpha: 2.0,
+            eta: 1.55,
+        }
+    }
+    // eumelanin concentration, about 0.3 for blond up to 8 for black hair
+    pub fn melanin(eumelanin: f64, beta: f64) -> Self {
+        Self::new(Color::new(0.419, 0.697, 1.37) * eumelanin, beta)
+    }
+}
+
+fn gauss() -> f64 {
+    let u1: f64 = tools::randf(1e-12, 1.0);
+    let u2: f64 = tools::randf(0.0, 1.0);
+    (-2.0 * u1.ln()).sqrt() * (2.0 * tools::PI * u2).cos()
+}
+
+// the fibre frame seen from wo and the energy of each lobe
+struct HairLobes {
+    t: Vec3,
+    n: Vec3,
+    b: Vec3,
+    sin_o: f64,
+    gamma_o: f64,
+    gamma_t: f64,
+    a: [Color; 4],
+    weight: [f64; 4],
+    total: f64,
+}
+
+fn gauss_pdf(x: f64, sigma: f64) -> f64 {
+    (-x * x / (2.0 * sigma * sigma)).exp() / (sigma * (2.0 * tools::PI).sqrt())
+}
+
+impl Hair {
+    fn lobes(&self, wo: Vec3, rec: &Hitrec) -> HairLobes {
+        let t: Vec3 = if rec.tangent.squared_length() > 0.0 {
+            rec.tangent.unit()
+        } else {
+            vec3::Onb::new(rec.nf()).u
+        };
+        // frame around the fibre with wo at azimuth 0
+        let sin_o: f64 = tools::clamp(wo * t, -1.0, 1.0);
+        let cos_o: f64 = (1.0 - sin_o * sin_o).sqrt();
+        let perp: Vec3 = wo - t * sin_o;
+        let n: Vec3 = if perp.squared_length() > 1e-12 {
+            perp.unit()
+        } else {
+            vec3::Onb::new(t).u
+        };
+        let b: Vec3 = Vec3::cross(t, n);
+
+        // refraction through the cylinder hit at offset h
+        let h: f64 = tools::clamp(2.0 * rec.v - 1.0, -1.0, 1.0);
+        let gamma_o: f64 = h.asin();
+        let sin_t: f64 = sin_o / self.eta;
+        let cos_t: f64 = (1.0 - sin_t * sin_t).sqrt();
+        let etap: f64 = (self.eta * self.eta - sin_o * sin_o).sqrt() / cos_o.max(1e-6);
+        let gamma_t: f64 = tools::clamp(h / etap, -1.0, 1.0).asin();
+        let path: Color = self.sigma_a * (2.0 * gamma_t.cos() / cos_t);
+        let tr: Color = Color::new((-path.x()).exp(), (-path.y()).exp(), (-path.z()).exp());
+
+        // energy of each lobe, the last one gathering all longer paths
+        let f: f64 = microfacet::fresnel_dielectric(cos_o * gamma_o.cos(), 1.0 / self.eta);
+        let mut a: [Color; 4] = [Color::ones() * f; 4];
+        a[1] = tr * ((1.0 - f) * (1.0 - f));
+        a[2] = Color::elemul(a[1], tr) * f;
+        let ft: Color = tr * f;
+        a[3] = Color::new(
+            a[2].x() * ft.x() / (1.0 - ft.x()),
+            a[2].y() * ft.y() / (1.0 - ft.y()),
+            a[2].z() * ft.z() / (1.0 - ft.z()),
+        );
+        let mut weight: [f64; 4] = [0.0; 4];
+        for (w, c) in weight.iter_mut().zip(a.iter()) {
+            *w = color::luminance(*c);
+        }
+        HairLobes {
+            t,
+            n,
+            b,
+            sin_o,
+            gamma_o,
+            gamma_t,
+            a,
+            weight,
+            total: weight.iter().sum(),
+        }
+    }
+
+    // mean longitudinal angle, spread and mean azimuth of lobe p: a mirror
+    // about the normal plane, shifted by the scales and blurred
+    fn lobe(&self, l: &HairLobes, p: usize) -> (f64, f64, f64) {
+        let shift: f64 = tools::dtr(self.alpha) * [2.0, -1.0, -3.0, 0.0][p];
+        let spread: f64 = self.beta * [1.0, 0.5, 2.0, 2.0][p];
+        let k: f64 = p as f64;
+        let phi: f64 = 2.0 * k * l.gamma_t - 2.0 * l.gamma_o + k * tools::PI;
+        (-l.sin_o.asin() + shift, spread, phi)
+    }
+
+    // f * cos_i and the pdf of scatter picking the unit direction wi, lobe
+    // by lobe the same gaussians scatter draws from
+    fn eval_pdf(&self, wo: Vec3, rec: &Hitrec, wi: Vec3) -> (Color, f64) {
+        let l = self.lobes(wo, rec);
+        if l.total <= 0.0 || self.beta <= 0.0 {
+            return (Color::zero(), 0.0);
+        }
+        let sin_i: f64 = tools::clamp(wi * l.t, -1.0, 1.0);
+        let theta_i: f64 = sin_i.asin();
+        let cos_i: f64 = theta_i.cos();
+        if cos_i < 1e-6 {
+            return (Color::zero(), 0.0);
+        }
+        let phi_i: f64 = (wi * l.b).atan2(wi * l.n);
+        let mut f: Color = Color::zero();
+        let mut pdf: f64 = 0.0;
+        for p in 0..4 {
+            let (theta, spread, phi) = self.lobe(&l, p);
+            let mp: f64 = gauss_pdf(theta_i - theta, spread);
+            let np: f64 = if p < 3 {
+                // wrapped around the circle
+                let d: f64 = (phi_i - phi).rem_euclid(2.0 * tools::PI);
+                (-2..=2)
+                    .map(|k| gauss_pdf(d + 2.0 * tools::PI * k as f64, spread))
+                    .sum()
+            } else {
+                1.0 / (2.0 * tools::PI)
+            };
+            // dw = cos(theta) dtheta dphi
+            let lobe_pdf: f64 = mp * np / cos_i;
+            f += l.a[p] * lobe_pdf;
+            pdf += l.weight[p] / l.total * lobe_pdf;
+        }
+        (f, pdf)
+    }
+}
+
+impl Material for Hair {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+        let wo: Vec3 = -r_in.diraction().unit();
+        let l = self.lobes(wo, rec);
+        if l.total <= 0.0 {
+            return false;
+        }
+        let mut pick: f64 = tools::randf(0.0, l.total);
+        let mut p: usize = 0;
+        while p < 3 && pick >= l.weight[p] {
+            pick -= l.weight[p];
+            p += 1;
+        }
+        att.copy(l.a[p] * (l.total / l.weight[p]));
+
+        let (theta, spread, phi) = self.lobe(&l, p);
+        let half_pi: f64 = tools::PI / 2.0;
+        let theta_i: f64 = tools::clamp(theta + spread * gauss(), -half_pi + 1e-4, half_pi - 1e-4);
+        let phi: f64 = if p < 3 {
+            phi + spread * gauss()
+        } else {
+            tools::randf(0.0, 2.0 * tools::PI)
+        };
+        let wi: Vec3 = l.t * theta_i.sin() + (l.n * phi.cos() + l.b * phi.sin()) * theta_i.cos();
+        scat.copy(Ray::wnew(rec.p(), wi, r_in.time(), r_in.lambda));
+        true
+    }
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        self.eval_pdf(-r_in.diraction().unit(), rec, wi.unit()).0
+    }
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        self.eval_pdf(-r_in.diraction().unit(), rec, wi.unit()).1
+    }
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        self.beta > 0.0
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct DiffuseLight {
-    emit: Arc<Texture>,
+    pub emit: Arc<Texture>,
+    pub intensity: f64,
+    pub two_sided: bool, // otherwise only the front face, the side the normal points to, emits
+    pub ies: Option<Arc<IesProfile>>, // around the emitting side's normal
 }
 
 impl DiffuseLight {
     pub fn new(emit: Arc<Texture>) -> Self {
-        Self { emit }
+        Self::snew(emit, 1.0, true)
     }
     pub fn cnew(c: Color) -> Self {
-        let arc = Arc::new(texture::SolidColor::new(c));
-        Self { emit: arc }
+        Self::snew(Arc::new(texture::SolidColor::new(c)), 1.0, true)
+    }
+    pub fn snew(emit: Arc<Texture>, intensity: f64, two_sided: bool) -> Self {
+        Self {
+            emit,
+            intensity,
+            two_sided,
+            ies: None,
+        }
+    }
+    // emission falls off with the angle to the surface normal like the
+    // profile does with the angle to its axis, peaking at emit * intensity
+    pub fn inew(emit: Arc<Texture>, intensity: f64, two_sided: bool, ies: Arc<IesProfile>) -> Self {
+        Self {
+            ies: Some(ies),
+            ..Self::snew(emit, intensity, two_sided)
+        }
+    }
+    // color of a black body at kelvin, with intensity as its luminance
+    pub fn tnew(kelvin: f64, intensity: f64, two_sided: bool) -> Self {
+        let c: Color = spectrum::blackbody_rgb(kelvin);
+        Self::snew(Arc::new(texture::SolidColor::new(c)), intensity, two_sided)
     }
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, r_in: Ray, rec: Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
         false
     }
-    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Color {
-        Vec3::elemul(self.emit.value(u, v, p), Vec3::ones()) //lighter
+    fn emitted(&self, r_in: &Ray, rec: &Hitrec) -> Color {
+        if !self.two_sided && !rec.front_face {
+            return Color::zero();
+        }
+        let mut scale: f64 = self.intensity;
+        if let Some(ies) = &self.ies {
+            // nf faces the viewer, which is the side the light leaves from
+            scale *= ies.value(&vec3::Onb::new(rec.nf()), &-r_in.diraction().unit());
+        }
+        self.emit.value(rec.u, rec.v, &rec.p) * scale
     }
 }
 
@@ -178,9 +1116,128 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, r_in: Ray, rec: Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
-        *scat = Ray::new(rec.p(), vec3::rand_in_unit_sphere(), r_in.time());
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+        *scat = Ray::wnew(
+            rec.p(),
+            vec3::rand_in_unit_sphere(),
+            r_in.time(),
+            r_in.lambda,
+        );
+        *att = self.lbc.value(rec.u, rec.v, &rec.p);
+        true
+    }
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        self.lbc.value(rec.u, rec.v, &rec.p) / (4.0 * tools::PI)
+    }
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        1.0 / (4.0 * tools::PI)
+    }
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        true
+    }
+}
+
+// scattering angle around the propagation direction d, g > 0 scatters forward
+pub fn sample_hg(d: Vec3, g: f64) -> Vec3 {
+    let r1: f64 = tools::randf(0.0, 1.0);
+    let r2: f64 = tools::randf(0.0, 1.0);
+    let cos_t: f64 = if g.abs() < 1e-3 {
+        1.0 - 2.0 * r1
+    } else {
+        let sq: f64 = (1.0 - g * g) / (1.0 - g + 2.0 * g * r1);
+        (1.0 + g * g - sq * sq) / (2.0 * g)
+    };
+    let cos_t: f64 = tools::clamp(cos_t, -1.0, 1.0);
+    let sin_t: f64 = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+    let phi: f64 = 2.0 * tools::PI * r2;
+    vec3::Onb::new(d).local(Vec3::new(sin_t * phi.cos(), sin_t * phi.sin(), cos_t))
+}
+
+// density of sample_hg over the sphere, cos_t against the propagation direction
+pub fn hg_phase(cos_t: f64, g: f64) -> f64 {
+    let denom: f64 = 1.0 + g * g - 2.0 * g * cos_t;
+    (1.0 - g * g) / (4.0 * tools::PI * denom * denom.sqrt())
+}
+
+#[derive(Debug, Clone)]
+pub struct HenyeyGreenstein {
+    lbc: Arc<Texture>,
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(lbc: Arc<Texture>, g: f64) -> Self {
+        Self { lbc, g }
+    }
+    pub fn cnew(c: Color, g: f64) -> Self {
+        Self {
+            lbc: Arc::new(texture::SolidColor::new(c)),
+            g,
+        }
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+        let dir: Vec3 = sample_hg(r_in.diraction().unit(), self.g);
+        *scat = Ray::wnew(rec.p(), dir, r_in.time(), r_in.lambda);
         *att = self.lbc.value(rec.u, rec.v, &rec.p);
         true
     }
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        self.lbc.value(rec.u, rec.v, &rec.p) * self.pdf(r_in, rec, wi)
+    }
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        hg_phase(r_in.diraction().unit() * *wi, self.g)
+    }
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        true
+    }
+}
+
+// blend of a forward and a backward lobe, w is the weight of the first
+#[derive(Debug, Clone)]
+pub struct DoubleHenyeyGreenstein {
+    lbc: Arc<Texture>,
+    g1: f64,
+    g2: f64,
+    w: f64,
+}
+
+impl DoubleHenyeyGreenstein {
+    pub fn new(lbc: Arc<Texture>, g1: f64, g2: f64, w: f64) -> Self {
+        Self { lbc, g1, g2, w }
+    }
+    pub fn cnew(c: Color, g1: f64, g2: f64, w: f64) -> Self {
+        Self {
+            lbc: Arc::new(texture::SolidColor::new(c)),
+            g1,
+            g2,
+            w,
+        }
+    }
+}
+
+impl Material for DoubleHenyeyGreenstein {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+        let g: f64 = if tools::randf(0.0, 1.0) < self.w {
+            self.g1
+        } else {
+            self.g2
+        };
+        let dir: Vec3 = sample_hg(r_in.diraction().unit(), g);
+        *scat = Ray::wnew(rec.p(), dir, r_in.time(), r_in.lambda);
+        *att = self.lbc.value(rec.u, rec.v, &rec.p);
+        true
+    }
+    fn eval(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> Color {
+        self.lbc.value(rec.u, rec.v, &rec.p) * self.pdf(r_in, rec, wi)
+    }
+    fn pdf(&self, r_in: &Ray, rec: &Hitrec, wi: &Vec3) -> f64 {
+        let cos_t: f64 = r_in.diraction().unit() * *wi;
+        self.w * hg_phase(cos_t, self.g1) + (1.0 - self.w) * hg_phase(cos_t, self.g2)
+    }
+    fn evaluable(&self, rec: &Hitrec) -> bool {
+        true
+    }
 }
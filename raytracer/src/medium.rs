@@ -0,0 +1,644 @@
+use crate::color::Color;
+use crate::material;
+use crate::material::Material;
+use crate::microfacet;
+use crate::perlin::Perlin;
+use crate::ray::Ray;
+use crate::shapes::Hitrec;
+use crate::shapes::Hittable;
+use crate::shapes::AABB;
+use crate::texture::Texture;
+use crate::tools;
+use crate::vec3::Vec3;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+pub trait Density: Debug + Send + Sync {
+    fn density(&self, p: &Vec3) -> f64;
+    // upper bound of density anywhere, the majorant for delta tracking
+    fn max_density(&self) -> f64;
+}
+
+// densities on a regular grid spanning [mini, maxi], trilinearly filtered
+#[derive(Debug, Clone)]
+pub struct GridDensity {
+    mini: Vec3,
+    maxi: Vec3,
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    data: Vec<f64>, // x fastest, then y, then z
+    max: f64,
+}
+
+impl GridDensity {
+    pub fn new(mini: Vec3, maxi: Vec3, nx: usize, ny: usize, nz: usize, data: Vec<f64>) -> Self {
+        assert!(
+            nx >= 2 && ny >= 2 && nz >= 2,
+            "grid needs two samples per axis"
+        );
+        assert_eq!(data.len(), nx * ny * nz, "grid size does not match data");
+        let max = data.iter().cloned().fold(0.0, f64::max);
+        Self {
+            mini,
+            maxi,
+            nx,
+            ny,
+            nz,
+            data,
+            max,
+        }
+    }
+    // bake a procedural field into an n^3 grid
+    pub fn fnew(mini: Vec3, maxi: Vec3, n: usize, field: &dyn Fn(&Vec3) -> f64) -> Self {
+        let mut data: Vec<f64> = Vec::with_capacity(n * n * n);
+        let step: Vec3 = (maxi - mini) / (n - 1) as f64;
+        for k in 0..n {
+            for j in 0..n {
+                for i in 0..n {
+                    let p = mini + Vec3::elemul(step, Vec3::new(i as f64, j as f64, k as f64));
+                    data.push(field(&p).max(0.0));
+                }
+            }
+        }
+        Self::new(mini, maxi, n, n, n, data)
+    }
+    fn at(&self, i: usize, j: usize, k: usize) -> f64 {
+        self.data[(k * self.ny + j) * self.nx + i]
+    }
+}
+
+impl Density for GridDensity {
+    fn density(&self, p: &Vec3) -> f64 {
+        let ext: Vec3 = self.maxi - self.mini;
+        let gx = (p.x() - self.mini.x()) / ext.x() * (self.nx - 1) as f64;
+        let gy = (p.y() - self.mini.y()) / ext.y() * (self.ny - 1) as f64;
+        let gz = (p.z() - self.mini.z()) / ext.z() * (self.nz - 1) as f64;
+        if gx < 0.0
+            || gy < 0.0
+            || gz < 0.0
+            || gx > (self.nx - 1) as f64
+            || gy > (self.ny - 1) as f64
+            || gz > (self.nz - 1) as f64
+        {
+            return 0.0;
+        }
+        let i = (gx as usize).min(self.nx - 2);
+        let j = (gy as usize).min(self.ny - 2);
+        let k = (gz as usize).min(self.nz - 2);
+        let (u, v, w) = (gx - i as f64, gy - j as f64, gz - k as f64);
+
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let wi = if di == 1 { u } else { 1.0 - u };
+                    let wj = if dj == 1 { v } else { 1.0 - v };
+                    let wk = if dk == 1 { w } else { 1.0 - w };
+                    accum += wi * wj * wk * self.at(i + di, j + dj, k + dk);
+                }
+            }
+        }
+        accum
+    }
+    fn max_density(&self) -> f64 {
+        self.max
+    }
+}
+
+// turbulence driven density, density * turb(scale * p)
+#[derive(Debug, Clone)]
+pub struct NoiseDensity {
+    noise: Perlin,
+    scale: f64,
+    density: f64,
+}
+
+impl NoiseDensity {
+    pub fn new(scale: f64, density: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            density,
+        }
+    }
+}
+
+impl Density for NoiseDensity {
+    fn density(&self, p: &Vec3) -> f64 {
+        self.density * self.noise.turb(&(*p * self.scale), 7)
+    }
+    fn max_density(&self) -> f64 {
+        // the octave weights of turb sum to less than 2
+        2.0 * self.density
+    }
+}
+
+// scattering inside a region whose boundary is handled by the caller
+pub trait Medium: Debug + Send + Sync {
+    // sample a scattering event along r between t0 and t1, all of it inside the medium
+    fn sample(&self, r: &Ray, t0: f64, t1: f64) -> Option<Hitrec>;
+    fn transmittance(&self, r: &Ray, t0: f64, t1: f64) -> f64;
+}
+
+fn scatter_rec(phase: &Arc<Material>, r: &Ray, t: f64) -> Hitrec {
+    let mut rec = Hitrec::new(phase.clone());
+    rec.t = t;
+    rec.p = r.at(t);
+    // there is no surface, face the incoming ray
+    rec.nf = -r.diraction().unit();
+    rec.front_face = true;
+    rec
+}
+
+#[derive(Debug, Clone)]
+pub struct Homogeneous {
+    phase_function: Arc<Material>,
+    neg_inv_density: f64,
+}
+
+impl Homogeneous {
+    pub fn new(d: f64, a: Arc<Texture>) -> Self {
+        Self::pnew(d, Arc::new(material::Isotropic::new(a)))
+    }
+    pub fn cnew(d: f64, c: Color) -> Self {
+        Self::pnew(d, Arc::new(material::Isotropic::cnew(c)))
+    }
+    pub fn pnew(d: f64, phase: Arc<Material>) -> Self {
+        Self {
+            phase_function: phase,
+            neg_inv_density: (-1.0 / d),
+        }
+    }
+}
+
+impl Medium for Homogeneous {
+    fn sample(&self, r: &Ray, t0: f64, t1: f64) -> Option<Hitrec> {
+        let ray_len = r.diraction().length();
+        let hit_dis = self.neg_inv_density * tools::randf(0.0, 1.0).ln();
+        if hit_dis > (t1 - t0) * ray_len {
+            return None;
+        }
+        Some(scatter_rec(&self.phase_function, r, t0 + hit_dis / ray_len))
+    }
+    fn transmittance(&self, r: &Ray, t0: f64, t1: f64) -> f64 {
+        ((t1 - t0) * r.diraction().length() / self.neg_inv_density).exp()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Heterogeneous {
+    density: Arc<Density>,
+    phase_function: Arc<Material>,
+}
+
+impl Heterogeneous {
+    pub fn new(d: Arc<Density>, a: Arc<Texture>) -> Self {
+        Self::pnew(d, Arc::new(material::Isotropic::new(a)))
+    }
+    pub fn cnew(d: Arc<Density>, c: Color) -> Self {
+        Self::pnew(d, Arc::new(material::Isotropic::cnew(c)))
+    }
+    pub fn pnew(d: Arc<Density>, phase: Arc<Material>) -> Self {
+        Self {
+            density: d,
+            phase_function: phase,
+        }
+    }
+}
+
+impl Medium for Heterogeneous {
+    // delta tracking: step through tentative collisions with the majorant and
+    // accept each as real with probability density / majorant
+    fn sample(&self, r: &Ray, t0: f64, t1: f64) -> Option<Hitrec> {
+        let majorant = self.density.max_density();
+        if majorant <= 0.0 {
+            return None;
+        }
+        let ray_len = r.diraction().length();
+        let mut t = t0;
+        loop {
+            t -= (1.0 - tools::randf(0.0, 1.0)).ln() / (majorant * ray_len);
+            if t >= t1 {
+                return None;
+            }
+            if tools::randf(0.0, 1.0) * majorant < self.density.density(&r.at(t)) {
+                return Some(scatter_rec(&self.phase_function, r, t));
+            }
+        }
+    }
+    // ratio tracking
+    fn transmittance(&self, r: &Ray, t0: f64, t1: f64) -> f64 {
+        let majorant = self.density.max_density();
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+        let ray_len = r.diraction().length();
+        let mut tr = 1.0;
+        let mut t = t0;
+        loop {
+            t -= (1.0 - tools::randf(0.0, 1.0)).ln() / (majorant * ray_len);
+            if t >= t1 {
+                return tr;
+            }
+            tr *= 1.0 - self.density.density(&r.at(t)) / majorant;
+        }
+    }
+}
+
+// keeps the ray going straight and only dims it, the null collisions of Absorbing
+#[derive(Debug, Clone)]
+struct Dim {
+    tint: Color,
+}
+
+impl Material for Dim {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+        *att = self.tint;
+        *scat = Ray::wnew(rec.p(), r_in.diraction(), r_in.time(), r_in.lambda);
+        true
+    }
+    fn null_collision(&self) -> bool {
+        true
+    }
+}
+
+// pure absorption with sigma_a per unit of distance for each channel, e.g. the
+// inside of tinted glass behind a MediumInterface. collisions come at the rate
+// of the strongest channel and each dims the ray by the share of that rate its
+// channel absorbs, which averages out to beer-lambert over whatever part of
+// the path lies inside, however the ray got there
+#[derive(Debug, Clone)]
+pub struct Absorbing {
+    sigma_a: Color,
+    majorant: f64,
+    dim: Arc<Material>,
+}
+
+impl Absorbing {
+    pub fn new(sigma_a: Color) -> Self {
+        let majorant: f64 = sigma_a.x().max(sigma_a.y()).max(sigma_a.z());
+        let tint: Color = if majorant > 0.0 {
+            Color::ones() - sigma_a / majorant
+        } else {
+            Color::ones()
+        };
+        Self {
+            sigma_a,
+            majorant,
+            dim: Arc::new(Dim { tint }),
+        }
+    }
+}
+
+impl Medium for Absorbing {
+    fn sample(&self, r: &Ray, t0: f64, t1: f64) -> Option<Hitrec> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+        let ray_len = r.diraction().length();
+        let hit_dis = -(1.0 - tools::randf(0.0, 1.0)).ln() / self.majorant;
+        if hit_dis > (t1 - t0) * ray_len {
+            return None;
+        }
+        Some(scatter_rec(&self.dim, r, t0 + hit_dis / ray_len))
+    }
+    // a single number can only follow the channels on average
+    fn transmittance(&self, r: &Ray, t0: f64, t1: f64) -> f64 {
+        let tr: Color = self.sigma_a * (-(t1 - t0) * r.diraction().length());
+        (tr.x().exp() + tr.y().exp() + tr.z().exp()) / 3.0
+    }
+}
+
+// the parts of [t_min, t_max] inside a closed boundary; walks every crossing
+// along the whole line and counts entries against exits, so non-convex and
+// overlapping boundaries as well as rays starting inside work
+pub fn interior_spans(
+    boundary: &Arc<Hittable>,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Vec<(f64, f64)> {
+    let mut spans: Vec<(f64, f64)> = Vec::new();
+    let mut depth: i32 = 0;
+    let mut start: f64 = -tools::INF;
+    for rec in boundary.hit_all(*r, -tools::INF, tools::INF) {
+        if rec.front_face {
+            if depth == 0 {
+                start = rec.t;
+            }
+            depth += 1;
+        } else if depth > 0 {
+            depth -= 1;
+            if depth == 0 {
+                let (t0, t1) = (start.max(t_min), rec.t.min(t_max));
+                if t0 < t1 {
+                    spans.push((t0, t1));
+                }
+            }
+        }
+        if rec.t >= t_max && depth == 0 {
+            break;
+        }
+    }
+    spans
+}
+
+// first scattering event of medium over a list of spans, ordered along the ray
+pub fn sample_spans(medium: &Medium, r: &Ray, spans: &[(f64, f64)]) -> Option<Hitrec> {
+    for (t0, t1) in spans {
+        if let Some(rec) = medium.sample(r, *t0, *t1) {
+            return Some(rec);
+        }
+    }
+    None
+}
+
+#[derive(Debug, Clone)]
+pub struct HeteroMedium {
+    boundary: Arc<Hittable>,
+    medium: Heterogeneous,
+}
+
+impl HeteroMedium {
+    pub fn new(b: Arc<Hittable>, d: Arc<Density>, a: Arc<Texture>) -> Self {
+        Self {
+            boundary: b,
+            medium: Heterogeneous::new(d, a),
+        }
+    }
+    pub fn cnew(b: Arc<Hittable>, d: Arc<Density>, c: Color) -> Self {
+        Self {
+            boundary: b,
+            medium: Heterogeneous::cnew(d, c),
+        }
+    }
+    pub fn pnew(b: Arc<Hittable>, d: Arc<Density>, phase: Arc<Material>) -> Self {
+        Self {
+            boundary: b,
+            medium: Heterogeneous::pnew(d, phase),
+        }
+    }
+
+    pub fn transmittance(&self, r: &Ray, t0: f64, t1: f64) -> f64 {
+        interior_spans(&self.boundary, r, t0, t1)
+            .iter()
+            .map(|(a, b)| self.medium.transmittance(r, *a, *b))
+            .product()
+    }
+}
+
+impl Hittable for HeteroMedium {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let spans = interior_spans(&self.boundary, &r, t_min, t_max);
+        sample_spans(&self.medium, &r, &spans)
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.boundary.bebox(t0, t1)
+    }
+}
+
+// a closed surface with a medium filling its inside, e.g. a glass sphere full of
+// fog; the surface keeps its own material and the medium only sees rays that
+// are inside, so refraction at the boundary works as usual
+#[derive(Debug, Clone)]
+pub struct MediumInterface {
+    boundary: Arc<Hittable>,
+    inside: Arc<Medium>,
+}
+
+impl MediumInterface {
+    pub fn new(boundary: Arc<Hittable>, inside: Arc<Medium>) -> Self {
+        Self { boundary, inside }
+    }
+}
+
+impl Hittable for MediumInterface {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        // look past t_max for the next crossing, it tells whether the ray starts inside
+        let surf = self.boundary.hit(r, t_min, tools::INF)?;
+        if !surf.front_face {
+            if let Some(rec) = self.inside.sample(&r, t_min, surf.t.min(t_max)) {
+                return Some(rec);
+            }
+        }
+        if surf.t < t_max {
+            Some(surf)
+        } else {
+            None
+        }
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.boundary.bebox(t0, t1)
+    }
+}
+
+// random walk subsurface scattering: a closed shape filled with a dense
+// medium behind a smooth dielectric surface, walked entirely inside one
+// scatter call so the path depth only counts entry and exit
+#[derive(Debug, Clone)]
+pub struct SubsurfaceWalk {
+    boundary: Arc<Hittable>,
+    sigma_s: Color,
+    sigma_a: Color,
+    ior: f64,
+    g: f64,
+}
+
+// steps a walk takes before russian roulette may end it; after that each
+// step survives with the throughput it still carries, so absorbing media end
+// walks early without darkening them on average, a walk that loses nothing
+// runs until it leaves the boundary
+const WALK_RR: usize = 64;
+
+fn channels(c: Color) -> [f64; 3] {
+    [c.x(), c.y(), c.z()]
+}
+
+impl SubsurfaceWalk {
+    // free flight with spectral mis: pick the channel by throughput, weight
+    // by the average pdf over all channels
+    fn flight(sigma_t: [f64; 3], thr: Color) -> f64 {
+        let w: [f64; 3] = channels(thr);
+        let sum: f64 = w[0] + w[1] + w[2];
+        let mut u: f64 = tools::randf(0.0, 1.0) * sum;
+        let mut c: usize = 2;
+        for (i, wi) in w.iter().enumerate() {
+            if u < *wi {
+                c = i;
+                break;
+            }
+            u -= wi;
+        }
+        -(1.0 - tools::randf(0.0, 1.0)).ln() / sigma_t[c]
+    }
+}
+
+impl Material for SubsurfaceWalk {
+    fn scatter(&self, r_in: &Ray, rec: &Hitrec, att: &mut Color, scat: &mut Ray) -> bool {
+        att.copy(Color::ones());
+        let unit_dir: Vec3 = r_in.diraction().unit();
+        let rate: f64 = if rec.front_face {
+            1.0 / self.ior
+        } else {
+            self.ior
+        };
+        let cos_i: f64 = -unit_dir * rec.nf();
+        if tools::randf(0.0, 1.0) < microfacet::fresnel_dielectric(cos_i, rate) {
+            let refec: Vec3 = Vec3::reflect(unit_dir, rec.nf());
+            scat.copy(Ray::wnew(rec.p(), refec, r_in.time(), r_in.lambda));
+            return true;
+        }
+        let refac: Vec3 = Vec3::refract(unit_dir, rec.nf(), rate);
+        if !rec.front_face {
+            // started inside, e.g. a camera in the wax
+            scat.copy(Ray::wnew(rec.p(), refac, r_in.time(), r_in.lambda));
+            return true;
+        }
+
+        let sigma_s: [f64; 3] = channels(self.sigma_s);
+        let sigma_t: [f64; 3] = channels(self.sigma_s + self.sigma_a);
+        let mut thr: Color = Color::ones();
+        let mut p: Vec3 = rec.p();
+        let mut dir: Vec3 = refac.unit();
+        // rays leaving the surface skip it, rays from a scattering event do
+        // not, so an event just short of the surface still finds it
+        let mut eps: f64 = 0.0001;
+        let mut steps: usize = 0;
+        loop {
+            steps += 1;
+            if steps > WALK_RR {
+                let q: f64 = thr.x().max(thr.y()).max(thr.z()).min(1.0);
+                if tools::randf(0.0, 1.0) >= q {
+                    return false;
+                }
+                thr = thr / q;
+            }
+            let ray = Ray::wnew(p, dir, r_in.time(), r_in.lambda);
+            // a boundary that is not closed lets the walk leak, drop the path
+            let exit = match self.boundary.hit(ray, eps, tools::INF) {
+                Some(rec) => rec,
+                None => return false,
+            };
+            let t: f64 = Self::flight(sigma_t, thr).min(exit.t);
+            let w: [f64; 3] = channels(thr);
+            let sum: f64 = w[0] + w[1] + w[2];
+            let mut next = [0.0; 3];
+            let mut pdf: f64 = 0.0;
+            for i in 0..3 {
+                let tr: f64 = (-sigma_t[i] * t).exp();
+                if t < exit.t {
+                    next[i] = w[i] * sigma_s[i] * tr;
+                    pdf += w[i] / sum * sigma_t[i] * tr;
+                } else {
+                    next[i] = w[i] * tr;
+                    pdf += w[i] / sum * tr;
+                }
+            }
+            if pdf <= 0.0 {
+                return false;
+            }
+            thr = Color::new(next[0], next[1], next[2]) / pdf;
+            if thr.x().max(thr.y()).max(thr.z()) <= 0.0 {
+                return false;
+            }
+
+            if t < exit.t {
+                p = ray.at(t);
+                dir = material::sample_hg(dir, self.g);
+                eps = 1e-9;
+                continue;
+            }
+            // reached the surface from inside, the normal faces the walk
+            let cos_o: f64 = -dir * exit.nf();
+            if tools::randf(0.0, 1.0) < microfacet::fresnel_dielectric(cos_o, self.ior) {
+                p = exit.p();
+                dir = Vec3::reflect(dir, exit.nf());
+                eps = 0.0001;
+                continue;
+            }
+            let out: Vec3 = Vec3::refract(dir, exit.nf(), self.ior);
+            att.copy(thr);
+            scat.copy(Ray::wnew(exit.p(), out, r_in.time(), r_in.lambda));
+            return true;
+        }
+    }
+}
+
+// a shape rendered with random walk subsurface scattering, its own material is ignored
+#[derive(Debug, Clone)]
+pub struct Subsurface {
+    boundary: Arc<Hittable>,
+    mat: Arc<Material>,
+}
+
+impl Subsurface {
+    // scattering and absorption coefficients per unit length
+    pub fn new(boundary: Arc<Hittable>, sigma_s: Color, sigma_a: Color, ior: f64, g: f64) -> Self {
+        let mat = Arc::new(SubsurfaceWalk {
+            boundary: boundary.clone(),
+            sigma_s,
+            sigma_a,
+            ior,
+            g,
+        });
+        Self { boundary, mat }
+    }
+    // single scattering albedo and mean free path per channel, in scene units
+    pub fn anew(boundary: Arc<Hittable>, albedo: Color, mfp: Color, ior: f64, g: f64) -> Self {
+        let sigma_t: Color = Color::new(1.0 / mfp.x(), 1.0 / mfp.y(), 1.0 / mfp.z());
+        let sigma_s: Color = Color::elemul(albedo, sigma_t);
+        Self::new(boundary, sigma_s, sigma_t - sigma_s, ior, g)
+    }
+}
+
+impl Hittable for Subsurface {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let mut rec = self.boundary.hit(r, t_min, t_max)?;
+        rec.mat = self.mat.clone();
+        Some(rec)
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.boundary.bebox(t0, t1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::Hitlist;
+    use crate::shapes::Sphere;
+    use crate::test_util::gray;
+
+    // along x: two overlapping balls covering [-1, 1.5] and a separate one [3, 4]
+    fn union() -> Arc<Hittable> {
+        let mut list = Hitlist::new();
+        list.add(Arc::new(Sphere::new(Vec3::zero(), 1.0, gray())));
+        list.add(Arc::new(Sphere::new(Vec3::new(1.0, 0.0, 0.0), 0.5, gray())));
+        list.add(Arc::new(Sphere::new(Vec3::new(3.5, 0.0, 0.0), 0.5, gray())));
+        Arc::new(list)
+    }
+    fn same(got: Vec<(f64, f64)>, want: &[(f64, f64)]) -> bool {
+        got.len() == want.len()
+            && got
+                .iter()
+                .zip(want.iter())
+                .all(|(g, w)| (g.0 - w.0).abs() < 1e-6 && (g.1 - w.1).abs() < 1e-6)
+    }
+
+    #[test]
+    fn test_interior_spans() {
+        let boundary = union();
+        let r = Ray::new(Vec3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let all = interior_spans(&boundary, &r, 0.0, tools::INF);
+        assert!(same(all, &[(9.0, 11.5), (13.0, 14.0)]));
+        // starting inside the overlap and stopping inside the far ball
+        let clipped = interior_spans(&boundary, &r, 10.0, 13.5);
+        assert!(same(clipped, &[(10.0, 11.5), (13.0, 13.5)]));
+        // the gap between the two parts is outside
+        assert!(interior_spans(&boundary, &r, 12.0, 12.5).is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+use crate::color::Color;
+use crate::tools;
+use crate::vec3::Onb;
+use crate::vec3::Vec3;
+
+// GGX / Trowbridge-Reitz helpers. cosines are taken against the
+// macro surface normal, alpha is the squared perceptual roughness.
+
+pub fn alpha(roughness: f64) -> f64 {
+    let r: f64 = tools::clamp(roughness, 0.0, 1.0);
+    if r * r < 0.001 {
+        0.001
+    } else {
+        r * r
+    }
+}
+
+pub fn ggx_d(cos_h: f64, alpha: f64) -> f64 {
+    if cos_h <= 0.0 {
+        return 0.0;
+    }
+    let a2: f64 = alpha * alpha;
+    let d: f64 = cos_h * cos_h * (a2 - 1.0) + 1.0;
+    a2 / (tools::PI * d * d)
+}
+
+pub fn smith_g1(cos_v: f64, alpha: f64) -> f64 {
+    let c: f64 = cos_v.abs();
+    if c <= 0.0 {
+        return 0.0;
+    }
+    let a2: f64 = alpha * alpha;
+    2.0 * c / (c + (a2 + (1.0 - a2) * c * c).sqrt())
+}
+
+pub fn smith_g(cos_o: f64, cos_i: f64, alpha: f64) -> f64 {
+    smith_g1(cos_o, alpha) * smith_g1(cos_i, alpha)
+}
+
+// half vector in the local frame (z up), distributed as D(h) * cos_h
+pub fn sample_ggx(alpha: f64) -> Vec3 {
+    let r1: f64 = tools::randf(0.0, 1.0);
+    let r2: f64 = tools::randf(0.0, 1.0);
+    let phi: f64 = 2.0 * tools::PI * r2;
+    let tan2: f64 = alpha * alpha * r1 / (1.0 - r1);
+    let cos_t: f64 = 1.0 / (1.0 + tan2).sqrt();
+    let sin_t: f64 = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+    Vec3::new(sin_t * phi.cos(), sin_t * phi.sin(), cos_t)
+}
+
+// sample a GGX reflection of wo around n, returning the direction,
+// f * cos / pdf without the fresnel term, and the cosine to the microfacet
+pub fn sample_reflection(wo: Vec3, n: Vec3, alpha: f64) -> Option<(Vec3, f64, f64)> {
+    let cos_o: f64 = wo * n;
+    let h: Vec3 = Onb::new(n).local(sample_ggx(alpha));
+    let cos_oh: f64 = wo * h;
+    if cos_o <= 0.0 || cos_oh <= 0.0 {
+        return None;
+    }
+    let wi: Vec3 = Vec3::reflect(-wo, h);
+    let cos_i: f64 = wi * n;
+    if cos_i <= 0.0 {
+        return None;
+    }
+    // the pdf of wi is D * cos_h / (4 * cos_oh), so only G and the jacobian remain
+    let weight: f64 = smith_g(cos_o, cos_i, alpha) * cos_oh / (cos_o * (h * n));
+    Some((wi, weight, cos_oh))
+}
+
+// the counterpart of sample_reflection for a given wi: f * cos_i without the
+// fresnel term, the pdf of sampling wi and the cosine to the microfacet
+pub fn eval_reflection(wo: Vec3, wi: Vec3, n: Vec3, alpha: f64) -> Option<(f64, f64, f64)> {
+    let cos_o: f64 = wo * n;
+    let cos_i: f64 = wi * n;
+    if cos_o <= 0.0 || cos_i <= 0.0 {
+        return None;
+    }
+    let h: Vec3 = (wo + wi).unit();
+    let cos_oh: f64 = wo * h;
+    if cos_oh <= 0.0 {
+        return None;
+    }
+    let d: f64 = ggx_d(h * n, alpha);
+    let f: f64 = d * smith_g(cos_o, cos_i, alpha) / (4.0 * cos_o);
+    Some((f, d * (h * n) / (4.0 * cos_oh), cos_oh))
+}
+
+// unpolarized fresnel reflectance, rate is etai over etat like Vec3::refract
+pub fn fresnel_dielectric(cos_i: f64, rate: f64) -> f64 {
+    let cos_i: f64 = tools::clamp(cos_i, 0.0, 1.0);
+    let sin2_t: f64 = rate * rate * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t: f64 = (1.0 - sin2_t).sqrt();
+    let eta: f64 = 1.0 / rate;
+    let rs: f64 = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp: f64 = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+pub fn schlick_color(cos_i: f64, f0: Color) -> Color {
+    let m: f64 = (1.0 - tools::clamp(cos_i, 0.0, 1.0)).powf(5.0);
+    f0 + (Color::ones() - f0) * m
+}
+
+fn fresnel_conductor_channel(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2: f64 = cos_i * cos_i;
+    let sin2: f64 = 1.0 - cos2;
+    let eta2: f64 = eta * eta;
+    let k2: f64 = k * k;
+
+    let t0: f64 = eta2 - k2 - sin2;
+    let a2b2: f64 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1: f64 = a2b2 + cos2;
+    let a: f64 = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t2: f64 = 2.0 * cos_i * a;
+    let rs: f64 = (t1 - t2) / (t1 + t2);
+
+    let t3: f64 = cos2 * a2b2 + sin2 * sin2;
+    let t4: f64 = t2 * sin2;
+    let rp: f64 = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+// per channel fresnel for a complex index of refraction eta + i k
+pub fn fresnel_conductor(cos_i: f64, eta: Color, k: Color) -> Color {
+    let cos_i: f64 = tools::clamp(cos_i, 0.0, 1.0);
+    Color::new(
+        fresnel_conductor_channel(cos_i, eta.x(), k.x()),
+        fresnel_conductor_channel(cos_i, eta.y(), k.y()),
+        fresnel_conductor_channel(cos_i, eta.z(), k.z()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ggx_normalized() {
+        // projected microfacet area covers the macro surface once:
+        // 2 pi times the integral of D(mu) mu over mu = cos_h in [0, 1]
+        for alpha in [0.05, 0.3, 1.0].iter() {
+            let n = 200000;
+            let mut sum: f64 = 0.0;
+            for i in 0..n {
+                let mu: f64 = (i as f64 + 0.5) / n as f64;
+                sum += ggx_d(mu, *alpha) * mu / n as f64;
+            }
+            assert!((2.0 * tools::PI * sum - 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_fresnel_normal() {
+        let r0: f64 = (1.5 - 1.0) / (1.5 + 1.0);
+        assert!((fresnel_dielectric(1.0, 1.0 / 1.5) - r0 * r0).abs() < 1e-9);
+        // total internal reflection leaving the glass at a grazing angle
+        assert_eq!(fresnel_dielectric(0.1, 1.5), 1.0);
+    }
+}
@@ -0,0 +1,337 @@
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::shapes::Hitrec;
+use crate::shapes::Hittable;
+use crate::shapes::AABB;
+use crate::tools;
+use crate::vec3::Onb;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// flat shapes that can be sampled by area, so they can be used as lights
+pub trait Planar: Hittable {
+    fn area(&self) -> f64;
+    // a point spread uniformly over the surface
+    fn sample(&self) -> Vec3;
+    // unit normal of the front side
+    fn normal(&self) -> Vec3;
+}
+
+// the plane through q spanned by the edges u and v, hits are reported in
+// the coordinates along the two edges
+#[derive(Debug, Clone, Copy)]
+pub struct Plane {
+    q: Vec3,
+    u: Vec3,
+    v: Vec3,
+    n: Vec3,
+    w: Vec3, // u x v over its squared length, turns offsets into edge coordinates
+    d: f64,
+}
+
+impl Plane {
+    pub fn new(q: Vec3, u: Vec3, v: Vec3) -> Self {
+        let cross: Vec3 = Vec3::cross(u, v);
+        let n: Vec3 = cross.unit();
+        Self {
+            q,
+            u,
+            v,
+            n,
+            w: cross / cross.squared_length(),
+            d: n * q,
+        }
+    }
+
+    // t and the coordinates along u and v where the ray meets the plane
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        let denom: f64 = self.n * r.diraction();
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t: f64 = (self.d - self.n * r.origin()) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let offset: Vec3 = r.at(t) - self.q;
+        let alpha: f64 = self.w * Vec3::cross(offset, self.v);
+        let beta: f64 = self.w * Vec3::cross(self.u, offset);
+        Some((t, alpha, beta))
+    }
+
+    fn rec(&self, r: &Ray, t: f64, u: f64, v: f64, mat: &Arc<Material>) -> Hitrec {
+        let mut rec = Hitrec::new(mat.clone());
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.u = u;
+        rec.v = v;
+        rec.set_face(*r, self.n);
+        rec
+    }
+
+    fn point(&self, alpha: f64, beta: f64) -> Vec3 {
+        self.q + self.u * alpha + self.v * beta
+    }
+    fn edge_area(&self) -> f64 {
+        Vec3::cross(self.u, self.v).length()
+    }
+}
+
+// bounds of a circle around ct in the plane with unit normal n
+pub fn circle_box(ct: Vec3, n: Vec3, rad: f64) -> AABB {
+    let ext = |x: f64| rad * (1.0 - x * x).max(0.0).sqrt();
+    let e = Vec3::new(ext(n.x()), ext(n.y()), ext(n.z()));
+    AABB::new(ct - e, ct + e)
+}
+
+// box around the points, padded so a flat shape along an axis keeps some thickness
+fn points_box(points: &[Vec3]) -> AABB {
+    let lit = 0.0001;
+    let mut mini = Vec3::ones() * tools::INF;
+    let mut maxi = Vec3::ones() * -tools::INF;
+    for p in points.iter() {
+        mini = Vec3::new(
+            mini.x().min(p.x()),
+            mini.y().min(p.y()),
+            mini.z().min(p.z()),
+        );
+        maxi = Vec3::new(
+            maxi.x().max(p.x()),
+            maxi.y().max(p.y()),
+            maxi.z().max(p.z()),
+        );
+    }
+    AABB::new(mini - Vec3::ones() * lit, maxi + Vec3::ones() * lit)
+}
+
+// a parallelogram with a corner at q and edges u and v, facing u x v
+#[derive(Debug, Clone)]
+pub struct Quad {
+    plane: Plane,
+    mat: Arc<Material>,
+}
+
+impl Quad {
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, mat: Arc<Material>) -> Self {
+        Self {
+            plane: Plane::new(q, u, v),
+            mat,
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let (t, a, b) = self.plane.hit(&r, t_min, t_max)?;
+        if a < 0.0 || a > 1.0 || b < 0.0 || b > 1.0 {
+            return None;
+        }
+        Some(self.plane.rec(&r, t, a, b, &self.mat))
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let p = &self.plane;
+        Some(points_box(&[
+            p.point(0.0, 0.0),
+            p.point(1.0, 0.0),
+            p.point(0.0, 1.0),
+            p.point(1.0, 1.0),
+        ]))
+    }
+}
+
+impl Planar for Quad {
+    fn area(&self) -> f64 {
+        self.plane.edge_area()
+    }
+    fn sample(&self) -> Vec3 {
+        self.plane
+            .point(tools::randf(0.0, 1.0), tools::randf(0.0, 1.0))
+    }
+    fn normal(&self) -> Vec3 {
+        self.plane.n
+    }
+}
+
+// the triangle a b c, facing (b - a) x (c - a); uv are the barycentrics of b and c
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    plane: Plane,
+    mat: Arc<Material>,
+}
+
+impl Triangle {
+    pub fn new(a: Vec3, b: Vec3, c: Vec3, mat: Arc<Material>) -> Self {
+        Self {
+            plane: Plane::new(a, b - a, c - a),
+            mat,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let (t, a, b) = self.plane.hit(&r, t_min, t_max)?;
+        if a < 0.0 || b < 0.0 || a + b > 1.0 {
+            return None;
+        }
+        Some(self.plane.rec(&r, t, a, b, &self.mat))
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let p = &self.plane;
+        Some(points_box(&[
+            p.point(0.0, 0.0),
+            p.point(1.0, 0.0),
+            p.point(0.0, 1.0),
+        ]))
+    }
+}
+
+impl Planar for Triangle {
+    fn area(&self) -> f64 {
+        self.plane.edge_area() / 2.0
+    }
+    fn sample(&self) -> Vec3 {
+        // fold the unit square onto the triangle
+        let (mut a, mut b) = (tools::randf(0.0, 1.0), tools::randf(0.0, 1.0));
+        if a + b > 1.0 {
+            a = 1.0 - a;
+            b = 1.0 - b;
+        }
+        self.plane.point(a, b)
+    }
+    fn normal(&self) -> Vec3 {
+        self.plane.n
+    }
+}
+
+// a flat disk facing along normal, or an annulus when inner is above 0;
+// u goes around the center and v outwards from the inner edge
+#[derive(Debug, Clone)]
+pub struct Disk {
+    plane: Plane,
+    rad: f64,
+    inner: f64,
+    mat: Arc<Material>,
+}
+
+impl Disk {
+    pub fn new(ct: Vec3, normal: Vec3, rad: f64, mat: Arc<Material>) -> Self {
+        Self::anew(ct, normal, rad, 0.0, mat)
+    }
+    // annulus between the two radii
+    pub fn anew(ct: Vec3, normal: Vec3, rad: f64, inner: f64, mat: Arc<Material>) -> Self {
+        // edges of one radius, so the plane coordinates are in units of rad
+        let onb = Onb::new(normal);
+        let (u, v) = if Vec3::cross(onb.u, onb.v) * normal > 0.0 {
+            (onb.u, onb.v)
+        } else {
+            (onb.v, onb.u)
+        };
+        Self {
+            plane: Plane::new(ct, u * rad, v * rad),
+            rad,
+            inner,
+            mat,
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let (t, a, b) = self.plane.hit(&r, t_min, t_max)?;
+        let rho: f64 = (a * a + b * b).sqrt() * self.rad;
+        if rho > self.rad || rho < self.inner {
+            return None;
+        }
+        let phi: f64 = b.atan2(a);
+        let phi: f64 = if phi < 0.0 {
+            phi + 2.0 * tools::PI
+        } else {
+            phi
+        };
+        let u: f64 = phi / (2.0 * tools::PI);
+        let v: f64 = (rho - self.inner) / (self.rad - self.inner);
+        Some(self.plane.rec(&r, t, u, v, &self.mat))
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let b = circle_box(self.plane.q, self.plane.n, self.rad);
+        Some(points_box(&[b.min(), b.max()]))
+    }
+}
+
+impl Planar for Disk {
+    fn area(&self) -> f64 {
+        tools::PI * (self.rad * self.rad - self.inner * self.inner)
+    }
+    fn sample(&self) -> Vec3 {
+        // uniform in area between the two radii
+        let (r0, r1) = (self.inner / self.rad, 1.0);
+        let rho: f64 = (r0 * r0 + tools::randf(0.0, 1.0) * (r1 * r1 - r0 * r0)).sqrt();
+        let phi: f64 = 2.0 * tools::PI * tools::randf(0.0, 1.0);
+        self.plane.point(rho * phi.cos(), rho * phi.sin())
+    }
+    fn normal(&self) -> Vec3 {
+        self.plane.n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gray;
+
+    fn down(x: f64, z: f64) -> Ray {
+        Ray::new(Vec3::new(x, 5.0, z), Vec3::new(0.0, -1.0, 0.0), 0.0)
+    }
+
+    #[test]
+    fn test_quad() {
+        // a sheared parallelogram in the y = 1 plane, facing up
+        let quad = Quad::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 1.0),
+            gray(),
+        );
+        assert!((quad.area() - 4.0).abs() < 1e-9);
+        assert!((quad.normal() - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+        let rec = quad.hit(down(1.0, 1.5), 0.001, tools::INF).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9 && rec.front_face);
+        assert!((rec.u - 0.5).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9);
+        assert!(quad.hit(down(1.0, 0.2), 0.001, tools::INF).is_none());
+    }
+
+    #[test]
+    fn test_triangle() {
+        let tri = Triangle::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            gray(),
+        );
+        assert!((tri.area() - 2.0).abs() < 1e-9);
+        let rec = tri.hit(down(0.5, 0.5), 0.001, tools::INF).unwrap();
+        assert!((rec.u - 0.25).abs() < 1e-9 && (rec.v - 0.25).abs() < 1e-9);
+        assert!(tri.hit(down(1.2, 1.2), 0.001, tools::INF).is_none());
+        for _ in 0..100 {
+            let p: Vec3 = tri.sample();
+            assert!(p.x() >= 0.0 && p.z() >= 0.0 && p.x() + p.z() <= 2.0 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_annulus() {
+        let ring = Disk::anew(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), 2.0, 1.0, gray());
+        assert!((ring.area() - 3.0 * tools::PI).abs() < 1e-9);
+        assert!(ring.hit(down(0.5, 0.0), 0.001, tools::INF).is_none());
+        let rec = ring.hit(down(1.5, 0.0), 0.001, tools::INF).unwrap();
+        assert!((rec.v - 0.5).abs() < 1e-9 && rec.front_face);
+        for _ in 0..100 {
+            let rho: f64 = ring.sample().length();
+            assert!(rho >= 1.0 - 1e-9 && rho <= 2.0 + 1e-9);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use crate::material::Material;
+use crate::planar::circle_box;
+use crate::ray::Ray;
+use crate::shapes::Hitrec;
+use crate::shapes::Hittable;
+use crate::shapes::AABB;
+use crate::tools;
+use crate::vec3::Onb;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// a frame with the shape's axis along local z, rays are carried into it
+// without normalizing so t is the same on both sides
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    origin: Vec3,
+    onb: Onb,
+}
+
+impl Frame {
+    fn new(origin: Vec3, axis: Vec3) -> Self {
+        Self {
+            origin,
+            onb: Onb::new(axis),
+        }
+    }
+    fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(*v * self.onb.u, *v * self.onb.v, *v * self.onb.w)
+    }
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        self.onb.local(*v)
+    }
+    fn ray(&self, r: &Ray) -> Ray {
+        Ray::wnew(
+            self.to_local(&(r.origin() - self.origin)),
+            self.to_local(&r.diraction()),
+            r.time(),
+            r.lambda,
+        )
+    }
+}
+
+// angle around local z mapped to [0, 1)
+fn azimuth(p: &Vec3) -> f64 {
+    let phi: f64 = p.y().atan2(p.x());
+    let phi: f64 = if phi < 0.0 {
+        phi + 2.0 * tools::PI
+    } else {
+        phi
+    };
+    phi / (2.0 * tools::PI)
+}
+
+fn grow(b: AABB, pad: f64) -> AABB {
+    AABB::new(b.min() - Vec3::ones() * pad, b.max() + Vec3::ones() * pad)
+}
+
+// nearest hit of a cap at local height z with radius rad
+fn cap(lr: &Ray, z: f64, rad: f64, t_min: f64, t_max: f64) -> Option<(f64, Vec3)> {
+    if lr.diraction().z() == 0.0 {
+        return None;
+    }
+    let t: f64 = (z - lr.origin().z()) / lr.diraction().z();
+    if t <= t_min || t >= t_max {
+        return None;
+    }
+    let p: Vec3 = lr.at(t);
+    if p.x() * p.x() + p.y() * p.y() > rad * rad {
+        return None;
+    }
+    Some((t, p))
+}
+
+// a cone cut between two parallel circles, radius r0 at p0 and r1 at p1;
+// equal radii make a cylinder and a zero radius a pointed cone
+#[derive(Debug, Clone)]
+pub struct Cone {
+    frame: Frame,
+    p1: Vec3,
+    h: f64,
+    r0: f64,
+    r1: f64,
+    capped: bool,
+    mat: Arc<Material>,
+}
+
+impl Cone {
+    pub fn new(p0: Vec3, r0: f64, p1: Vec3, r1: f64, capped: bool, mat: Arc<Material>) -> Self {
+        Self {
+            frame: Frame::new(p0, p1 - p0),
+            p1,
+            h: (p1 - p0).length(),
+            r0,
+            r1,
+            capped,
+            mat,
+        }
+    }
+
+    // radius at local height z
+    fn radius(&self, z: f64) -> f64 {
+        self.r0 + (self.r1 - self.r0) * z / self.h
+    }
+
+    fn side(&self, lr: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Vec3)> {
+        // x^2 + y^2 = (r0 + k z)^2
+        let k: f64 = (self.r1 - self.r0) / self.h;
+        let (o, d) = (lr.origin(), lr.diraction());
+        let rz: f64 = self.r0 + k * o.z();
+        let a: f64 = d.x() * d.x() + d.y() * d.y() - k * k * d.z() * d.z();
+        let b: f64 = 2.0 * (o.x() * d.x() + o.y() * d.y() - k * rz * d.z());
+        let c: f64 = o.x() * o.x() + o.y() * o.y() - rz * rz;
+        for t in tools::solve_quadratic(a, b, c) {
+            if t <= t_min || t >= t_max {
+                continue;
+            }
+            let p: Vec3 = lr.at(t);
+            if p.z() >= 0.0 && p.z() <= self.h {
+                return Some((t, p));
+            }
+        }
+        None
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let lr: Ray = self.frame.ray(&r);
+        let k: f64 = (self.r1 - self.r0) / self.h;
+        let mut closest: f64 = t_max;
+        let mut found: Option<(Vec3, f64, f64)> = None; // local normal and uv
+
+        if let Some((t, p)) = self.side(&lr, t_min, closest) {
+            closest = t;
+            let n = Vec3::new(p.x(), p.y(), -k * self.radius(p.z()));
+            found = Some((n, azimuth(&p), p.z() / self.h));
+        }
+        if self.capped {
+            for (z, rad, nz) in [(0.0, self.r0, -1.0), (self.h, self.r1, 1.0)].iter() {
+                if *rad <= 0.0 {
+                    continue;
+                }
+                if let Some((t, p)) = cap(&lr, *z, *rad, t_min, closest) {
+                    closest = t;
+                    let rho: f64 = (p.x() * p.x() + p.y() * p.y()).sqrt();
+                    found = Some((Vec3::new(0.0, 0.0, *nz), azimuth(&p), rho / rad));
+                }
+            }
+        }
+
+        let (n, u, v) = found?;
+        let mut rec = Hitrec::new(self.mat.clone());
+        rec.t = closest;
+        rec.p = r.at(closest);
+        rec.u = u;
+        rec.v = v;
+        rec.set_face(r, self.frame.to_world(&n).unit());
+        Some(rec)
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let n: Vec3 = self.frame.onb.w;
+        Some(AABB::merge(
+            circle_box(self.frame.origin, n, self.r0),
+            circle_box(self.p1, n, self.r1),
+        ))
+    }
+}
+
+// a circular tube from p0 to p1, closed at both ends when capped
+#[derive(Debug, Clone)]
+pub struct Cylinder {
+    cone: Cone,
+}
+
+impl Cylinder {
+    pub fn new(p0: Vec3, p1: Vec3, rad: f64, capped: bool, mat: Arc<Material>) -> Self {
+        Self {
+            cone: Cone::new(p0, rad, p1, rad, capped, mat),
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        self.cone.hit(r, t_min, t_max)
+    }
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.cone.bebox(t0, t1)
+    }
+}
+
+// a ring around the axis, major radius to the center of the tube and minor
+// radius of the tube itself
+#[derive(Debug, Clone)]
+pub struct Torus {
+    frame: Frame,
+    major: f64,
+    minor: f64,
+    mat: Arc<Material>,
+}
+
+impl Torus {
+    pub fn new(ct: Vec3, axis: Vec3, major: f64, minor: f64, mat: Arc<Material>) -> Self {
+        Self {
+            frame: Frame::new(ct, axis),
+            major,
+            minor,
+            mat,
+        }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let lr: Ray = self.frame.ray(&r);
+        let len: f64 = lr.diraction().length();
+        let d: Vec3 = lr.diraction() / len;
+
+        // start the quartic at the bounding sphere, far origins ruin its precision
+        let bound: f64 = self.major + self.minor;
+        let b: f64 = lr.origin() * d;
+        let c: f64 = lr.origin().squared_length() - bound * bound;
+        if b * b - c < 0.0 {
+            return None;
+        }
+        let shift: f64 = (-b - (b * b - c).sqrt()).max(0.0);
+        let o: Vec3 = lr.origin() + d * shift;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + y^2) along the unit direction
+        let r2: f64 = self.major * self.major;
+        let e: f64 = o.squared_length() + r2 - self.minor * self.minor;
+        let f: f64 = o * d;
+        let roots = tools::solve_quartic(
+            4.0 * f,
+            2.0 * e + 4.0 * f * f - 4.0 * r2 * (d.x() * d.x() + d.y() * d.y()),
+            4.0 * e * f - 8.0 * r2 * (o.x() * d.x() + o.y() * d.y()),
+            e * e - 4.0 * r2 * (o.x() * o.x() + o.y() * o.y()),
+        );
+        let t: f64 = roots
+            .iter()
+            .map(|s| (s + shift) / len)
+            .find(|t| *t > t_min && *t < t_max)?;
+
+        let p: Vec3 = lr.at(t);
+        let rho: f64 = (p.x() * p.x() + p.y() * p.y()).sqrt();
+        // away from the circle through the middle of the tube
+        let ring: Vec3 = Vec3::new(p.x(), p.y(), 0.0) * (self.major / rho);
+        let n: Vec3 = (p - ring).unit();
+
+        let mut rec = Hitrec::new(self.mat.clone());
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.u = azimuth(&p);
+        rec.v = p.z().atan2(rho - self.major) / (2.0 * tools::PI) + 0.5;
+        rec.set_face(r, self.frame.to_world(&n));
+        Some(rec)
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        Some(grow(
+            circle_box(self.frame.origin, self.frame.onb.w, self.major),
+            self.minor,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gray;
+
+    #[test]
+    fn test_quartic() {
+        // (x - 1)(x - 2)(x + 3)(x - 0.5)
+        let roots = tools::solve_quartic(-0.5, -7.0, 9.5, -3.0);
+        let want = [-3.0, 0.5, 1.0, 2.0];
+        assert_eq!(roots.len(), 4);
+        for (r, w) in roots.iter().zip(want.iter()) {
+            assert!((r - w).abs() < 1e-9);
+        }
+        // x^4 + 1 has no real roots
+        assert!(tools::solve_quartic(0.0, 0.0, 0.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_torus() {
+        let torus = Torus::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), 3.0, 1.0, gray());
+        // straight through the ring across its middle plane
+        let r = Ray::new(Vec3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let rec = torus.hit(r, 0.001, tools::INF).unwrap();
+        assert!((rec.t - 6.0).abs() < 1e-9);
+        assert!((rec.nf - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-9);
+        // the hole, then the inner wall of the far side
+        let rec = torus.hit(r, 8.0, tools::INF).unwrap();
+        assert!((rec.t - 12.0).abs() < 1e-9);
+        // down the axis through the hole
+        let r = Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        assert!(torus.hit(r, 0.001, tools::INF).is_none());
+    }
+
+    #[test]
+    fn test_cone() {
+        let cone = Cone::new(
+            Vec3::zero(),
+            1.0,
+            Vec3::new(0.0, 2.0, 0.0),
+            0.0,
+            true,
+            gray(),
+        );
+        // halfway up the side the radius is 0.5 and the normal leans up
+        let r = Ray::new(Vec3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0), 0.0);
+        let rec = cone.hit(r, 0.001, tools::INF).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-9);
+        assert!((rec.nf - Vec3::new(2.0, 1.0, 0.0).unit()).length() < 1e-9);
+        // the base cap from below
+        let r = Ray::new(Vec3::new(0.2, -3.0, 0.1), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let rec = cone.hit(r, 0.001, tools::INF).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-9 && rec.front_face);
+        assert!((rec.nf - Vec3::new(0.0, -1.0, 0.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_cylinder() {
+        let cyl = Cylinder::new(Vec3::zero(), Vec3::new(0.0, 0.0, 4.0), 1.0, false, gray());
+        let r = Ray::new(Vec3::new(0.0, -5.0, 1.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let rec = cyl.hit(r, 0.001, tools::INF).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9 && (rec.v - 0.25).abs() < 1e-9);
+        // an open tube is seen from inside through its end
+        let r = Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.3, 1.0), 0.0);
+        assert!(!cyl.hit(r, 0.001, tools::INF).unwrap().front_face);
+    }
+}
@@ -5,16 +5,31 @@ pub struct Ray {
     pub org: Vec3,
     pub dir: Vec3,
     pub tm: f64,
+    pub lambda: f64, // sampled wavelength in nm, 0 when the path is not spectral
 }
 
 impl Ray {
     pub fn new(org: Vec3, dir: Vec3, tm: f64) -> Self {
-        Ray { org, dir, tm }
+        Ray {
+            org,
+            dir,
+            tm,
+            lambda: 0.0,
+        }
+    }
+    pub fn wnew(org: Vec3, dir: Vec3, tm: f64, lambda: f64) -> Self {
+        Ray {
+            org,
+            dir,
+            tm,
+            lambda,
+        }
     }
     pub fn copy(&mut self, other: Self) {
         self.org.copy(other.origin());
         self.dir.copy(other.diraction());
         self.tm = other.time();
+        self.lambda = other.lambda;
     }
 
     pub fn origin(&self) -> Vec3 {
@@ -0,0 +1,454 @@
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::shapes::Hitrec;
+use crate::shapes::Hittable;
+use crate::shapes::Sphere;
+use crate::shapes::AABB;
+use crate::tools;
+use crate::vec3::Vec3;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+// a signed distance field: negative inside, never more than the true
+// distance to the surface once divided by lipschitz
+pub trait Sdf: Debug + Send + Sync {
+    fn dist(&self, p: &Vec3) -> f64;
+    // a box the whole surface lies in
+    fn bound(&self) -> AABB;
+    // how much faster than the distance the field may change
+    fn lipschitz(&self) -> f64 {
+        1.0
+    }
+}
+
+fn grow(b: AABB, pad: f64) -> AABB {
+    AABB::new(b.min() - Vec3::ones() * pad, b.max() + Vec3::ones() * pad)
+}
+
+fn vmax(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()))
+}
+
+// polynomial smooth minimum, blending over k and never above min(a, b)
+fn smin(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h: f64 = (k - (a - b).abs()).max(0.0) / k;
+    a.min(b) - h * h * k / 4.0
+}
+
+#[derive(Debug, Clone)]
+pub struct SdfSphere {
+    ct: Vec3,
+    rad: f64,
+}
+
+impl SdfSphere {
+    pub fn new(ct: Vec3, rad: f64) -> Self {
+        Self { ct, rad }
+    }
+}
+
+impl Sdf for SdfSphere {
+    fn dist(&self, p: &Vec3) -> f64 {
+        (*p - self.ct).length() - self.rad
+    }
+    fn bound(&self) -> AABB {
+        let r = Vec3::ones() * self.rad;
+        AABB::new(self.ct - r, self.ct + r)
+    }
+}
+
+// a box of the given half sizes, its edges rounded off by round
+#[derive(Debug, Clone)]
+pub struct SdfBox {
+    ct: Vec3,
+    half: Vec3,
+    round: f64,
+}
+
+impl SdfBox {
+    pub fn new(ct: Vec3, half: Vec3, round: f64) -> Self {
+        Self { ct, half, round }
+    }
+}
+
+impl Sdf for SdfBox {
+    fn dist(&self, p: &Vec3) -> f64 {
+        let d: Vec3 = *p - self.ct;
+        let q: Vec3 = Vec3::new(d.x().abs(), d.y().abs(), d.z().abs()) - self.half
+            + Vec3::ones() * self.round;
+        vmax(q, Vec3::zero()).length() + q.x().max(q.y()).max(q.z()).min(0.0) - self.round
+    }
+    fn bound(&self) -> AABB {
+        AABB::new(self.ct - self.half, self.ct + self.half)
+    }
+}
+
+// a ring lying in the xz plane around ct
+#[derive(Debug, Clone)]
+pub struct SdfTorus {
+    ct: Vec3,
+    major: f64,
+    minor: f64,
+}
+
+impl SdfTorus {
+    pub fn new(ct: Vec3, major: f64, minor: f64) -> Self {
+        Self { ct, major, minor }
+    }
+}
+
+impl Sdf for SdfTorus {
+    fn dist(&self, p: &Vec3) -> f64 {
+        let d: Vec3 = *p - self.ct;
+        let ring: f64 = (d.x() * d.x() + d.z() * d.z()).sqrt() - self.major;
+        (ring * ring + d.y() * d.y()).sqrt() - self.minor
+    }
+    fn bound(&self) -> AABB {
+        let r: f64 = self.major + self.minor;
+        let e = Vec3::new(r, self.minor, r);
+        AABB::new(self.ct - e, self.ct + e)
+    }
+}
+
+// both shapes melted together over a blend distance k, 0 for a sharp union
+#[derive(Debug, Clone)]
+pub struct SmoothUnion {
+    a: Arc<Sdf>,
+    b: Arc<Sdf>,
+    k: f64,
+}
+
+impl SmoothUnion {
+    pub fn new(a: Arc<Sdf>, b: Arc<Sdf>, k: f64) -> Self {
+        Self { a, b, k }
+    }
+}
+
+impl Sdf for SmoothUnion {
+    fn dist(&self, p: &Vec3) -> f64 {
+        smin(self.a.dist(p), self.b.dist(p), self.k)
+    }
+    fn bound(&self) -> AABB {
+        // the blend swells the surface by at most k / 4
+        grow(AABB::merge(self.a.bound(), self.b.bound()), self.k / 4.0)
+    }
+    fn lipschitz(&self) -> f64 {
+        self.a.lipschitz().max(self.b.lipschitz())
+    }
+}
+
+// a with b carved out, the cut edge rounded over k
+#[derive(Debug, Clone)]
+pub struct SmoothSubtraction {
+    a: Arc<Sdf>,
+    b: Arc<Sdf>,
+    k: f64,
+}
+
+impl SmoothSubtraction {
+    pub fn new(a: Arc<Sdf>, b: Arc<Sdf>, k: f64) -> Self {
+        Self { a, b, k }
+    }
+}
+
+impl Sdf for SmoothSubtraction {
+    fn dist(&self, p: &Vec3) -> f64 {
+        -smin(-self.a.dist(p), self.b.dist(p), self.k)
+    }
+    fn bound(&self) -> AABB {
+        self.a.bound()
+    }
+    fn lipschitz(&self) -> f64 {
+        self.a.lipschitz().max(self.b.lipschitz())
+    }
+}
+
+// copies of a shape sitting at the origin every period along each axis,
+// count more on either side; a zero period leaves that axis alone
+#[derive(Debug, Clone)]
+pub struct Repeat {
+    inner: Arc<Sdf>,
+    period: Vec3,
+    count: Vec3,
+}
+
+impl Repeat {
+    pub fn new(inner: Arc<Sdf>, period: Vec3, count: Vec3) -> Self {
+        Self {
+            inner,
+            period,
+            count,
+        }
+    }
+}
+
+impl Sdf for Repeat {
+    fn dist(&self, p: &Vec3) -> f64 {
+        let fold = |x: f64, c: f64, n: f64| {
+            if c == 0.0 {
+                x
+            } else {
+                x - c * tools::clamp((x / c).round(), -n, n)
+            }
+        };
+        let q = Vec3::new(
+            fold(p.x(), self.period.x(), self.count.x()),
+            fold(p.y(), self.period.y(), self.count.y()),
+            fold(p.z(), self.period.z(), self.count.z()),
+        );
+        self.inner.dist(&q)
+    }
+    fn bound(&self) -> AABB {
+        let b = self.inner.bound();
+        let e = Vec3::elemul(self.period, self.count);
+        let e = Vec3::new(e.x().abs(), e.y().abs(), e.z().abs());
+        AABB::new(b.min() - e, b.max() + e)
+    }
+    fn lipschitz(&self) -> f64 {
+        self.inner.lipschitz()
+    }
+}
+
+// a shape wrung around the y axis, turning by rate radians per unit of height
+#[derive(Debug, Clone)]
+pub struct Twist {
+    inner: Arc<Sdf>,
+    rate: f64,
+    reach: f64, // farthest the inner shape gets from the y axis
+}
+
+impl Twist {
+    pub fn new(inner: Arc<Sdf>, rate: f64) -> Self {
+        let b = inner.bound();
+        let mut reach: f64 = 0.0;
+        for x in [b.min().x(), b.max().x()].iter() {
+            for z in [b.min().z(), b.max().z()].iter() {
+                reach = reach.max((x * x + z * z).sqrt());
+            }
+        }
+        Self { inner, rate, reach }
+    }
+}
+
+impl Sdf for Twist {
+    fn dist(&self, p: &Vec3) -> f64 {
+        let (s, c) = (self.rate * p.y()).sin_cos();
+        let q = Vec3::new(c * p.x() + s * p.z(), p.y(), -s * p.x() + c * p.z());
+        self.inner.dist(&q)
+    }
+    fn bound(&self) -> AABB {
+        let b = self.inner.bound();
+        AABB::new(
+            Vec3::new(-self.reach, b.min().y(), -self.reach),
+            Vec3::new(self.reach, b.max().y(), self.reach),
+        )
+    }
+    fn lipschitz(&self) -> f64 {
+        // the twist stretches space sideways by up to rate times the radius
+        let stretch: f64 = self.rate * self.reach;
+        self.inner.lipschitz() * (1.0 + stretch * stretch).sqrt()
+    }
+}
+
+const MAX_STEPS: usize = 512;
+const EPS: f64 = 1e-5;
+
+// an sdf rendered by sphere tracing, usable with any material
+#[derive(Debug, Clone)]
+pub struct SdfShape {
+    sdf: Arc<Sdf>,
+    bound: AABB,
+    mat: Arc<Material>,
+}
+
+impl SdfShape {
+    pub fn new(sdf: Arc<Sdf>, mat: Arc<Material>) -> Self {
+        Self {
+            bound: grow(sdf.bound(), 0.001),
+            sdf,
+            mat,
+        }
+    }
+
+    // where the ray is inside the bound, clipped to [t_min, t_max]
+    fn clip(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let (mut t0, mut t1) = (t_min, t_max);
+        let (o, d) = (r.origin(), r.diraction());
+        let lo = self.bound.min();
+        let hi = self.bound.max();
+        for (o, d, lo, hi) in [
+            (o.x(), d.x(), lo.x(), hi.x()),
+            (o.y(), d.y(), lo.y(), hi.y()),
+            (o.z(), d.z(), lo.z(), hi.z()),
+        ]
+        .iter()
+        {
+            let inv: f64 = 1.0 / d;
+            let (mut a, mut b) = ((lo - o) * inv, (hi - o) * inv);
+            if inv < 0.0 {
+                std::mem::swap(&mut a, &mut b);
+            }
+            t0 = t0.max(a);
+            t1 = t1.min(b);
+            if t1 <= t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
+    // central differences of the field, pointing out of the shape
+    fn normal(&self, p: &Vec3) -> Vec3 {
+        let h: f64 = 1e-4;
+        let d = |x: f64, y: f64, z: f64| self.sdf.dist(&(*p + Vec3::new(x, y, z)));
+        Vec3::new(
+            d(h, 0.0, 0.0) - d(-h, 0.0, 0.0),
+            d(0.0, h, 0.0) - d(0.0, -h, 0.0),
+            d(0.0, 0.0, h) - d(0.0, 0.0, -h),
+        )
+        .unit()
+    }
+}
+
+impl Hittable for SdfShape {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let (t0, t1) = self.clip(&r, t_min, t_max)?;
+        // march on the side the ray starts, so rays leaving the inside find the exit.
+        // a ray starting on the surface, e.g. scattered off it, takes the side
+        // it heads into and must leave the surface before it can hit again
+        let d0: f64 = self.sdf.dist(&r.at(t0));
+        let mut left: bool = d0.abs() >= EPS;
+        let side: f64 = if left {
+            d0.signum()
+        } else if self.normal(&r.at(t0)) * r.diraction() < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        let speed: f64 = r.diraction().length() * self.sdf.lipschitz();
+        let mut t: f64 = t0;
+        for _ in 0..MAX_STEPS {
+            let d: f64 = side * self.sdf.dist(&r.at(t));
+            if d < EPS && left {
+                let mut rec = Hitrec::new(self.mat.clone());
+                rec.t = t;
+                rec.p = r.at(t);
+                let n: Vec3 = self.normal(&rec.p);
+                rec.set_face(r, n);
+                Sphere::get_uv(&n, &mut rec.u, &mut rec.v);
+                return Some(rec);
+            }
+            left = left || d >= EPS;
+            t += d.max(EPS) / speed;
+            if t >= t1 {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        Some(self.bound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gray;
+
+    fn inside(b: &AABB, p: &Vec3) -> bool {
+        p.x() >= b.min().x()
+            && p.x() <= b.max().x()
+            && p.y() >= b.min().y()
+            && p.y() <= b.max().y()
+            && p.z() >= b.min().z()
+            && p.z() <= b.max().z()
+    }
+
+    #[test]
+    fn test_sphere() {
+        let ct = Vec3::new(1.0, 2.0, -1.0);
+        let traced = SdfShape::new(Arc::new(SdfSphere::new(ct, 1.5)), gray());
+        let exact = Sphere::new(ct, 1.5, gray());
+        for i in 0..50 {
+            let o = Vec3::new(-6.0, 0.1 * i as f64, 3.0);
+            let r = Ray::new(o, ct - o + Vec3::new(0.0, 0.02 * i as f64 - 0.5, 0.3), 0.0);
+            match (
+                traced.hit(r, 0.001, tools::INF),
+                exact.hit(r, 0.001, tools::INF),
+            ) {
+                (Some(a), Some(b)) => {
+                    assert!((a.t - b.t).abs() < 1e-4);
+                    assert!((a.nf - b.nf).length() < 1e-3);
+                    assert!(a.front_face && b.front_face);
+                }
+                (None, None) => {}
+                _ => panic!("sphere tracing disagrees with the sphere"),
+            }
+        }
+        // from the inside out
+        let r = Ray::new(ct, Vec3::new(0.0, 0.0, 2.0), 0.0);
+        let rec = traced.hit(r, 0.001, tools::INF).unwrap();
+        assert!((rec.t - 0.75).abs() < 1e-4 && !rec.front_face);
+
+        // rays starting where a hit left off, with no t_min to get clear:
+        // heading out they miss, heading in they find the far side
+        let o = ct + Vec3::new(0.0, 0.0, -5.0);
+        let rec = traced
+            .hit(Ray::new(o, ct - o, 0.0), 0.001, tools::INF)
+            .unwrap();
+        let away = Ray::new(rec.p, Vec3::new(0.3, 0.2, -1.0), 0.0);
+        assert!(traced.hit(away, 0.0, tools::INF).is_none());
+        let through = Ray::new(rec.p, Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let rec = traced.hit(through, 0.0, tools::INF).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-4 && !rec.front_face);
+    }
+
+    #[test]
+    fn test_box_and_torus() {
+        let b = SdfBox::new(Vec3::zero(), Vec3::new(1.0, 2.0, 3.0), 0.0);
+        assert!((b.dist(&Vec3::new(3.0, 0.0, 0.0)) - 2.0).abs() < 1e-9);
+        assert!((b.dist(&Vec3::new(0.0, 0.5, 0.0)) + 1.0).abs() < 1e-9);
+        assert!((b.dist(&Vec3::new(4.0, 6.0, 0.0)) - 5.0).abs() < 1e-9);
+        let t = SdfTorus::new(Vec3::zero(), 2.0, 0.5);
+        assert!(t.dist(&Vec3::new(2.0, 0.0, 0.0)) + 0.5 < 1e-9);
+        assert!((t.dist(&Vec3::zero()) - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_bounds() {
+        // every surface point the combinators produce lies in their bound
+        let ball: Arc<Sdf> = Arc::new(SdfSphere::new(Vec3::zero(), 0.4));
+        let bar: Arc<Sdf> = Arc::new(SdfBox::new(Vec3::zero(), Vec3::new(0.3, 1.5, 0.6), 0.05));
+        let shapes: Vec<Arc<Sdf>> = vec![
+            Arc::new(SmoothUnion::new(ball.clone(), bar.clone(), 0.5)),
+            Arc::new(SmoothSubtraction::new(bar.clone(), ball.clone(), 0.2)),
+            Arc::new(Repeat::new(
+                ball,
+                Vec3::new(1.0, 0.0, 1.0),
+                Vec3::new(2.0, 0.0, 1.0),
+            )),
+            Arc::new(Twist::new(bar, 1.5)),
+        ];
+        for sdf in shapes.iter() {
+            let b = sdf.bound();
+            let shape = SdfShape::new(sdf.clone(), gray());
+            for i in 0..400 {
+                let dir = Vec3::new(
+                    (i as f64 * 0.37).sin(),
+                    (i as f64 * 0.91).cos(),
+                    (i as f64 * 0.13).sin(),
+                );
+                let r = Ray::new(Vec3::new(0.0, 0.2, 0.0) - dir * 10.0, dir, 0.0);
+                if let Some(rec) = shape.hit(r, 0.001, tools::INF) {
+                    assert!(sdf.dist(&rec.p).abs() < 1e-3);
+                    assert!(inside(&grow(b, 1e-3), &rec.p));
+                }
+            }
+        }
+    }
+}
@@ -2,6 +2,7 @@ use crate::color::Color;
 use crate::material;
 use crate::material::Material;
 use crate::material::Neg;
+use crate::medium;
 use crate::ray::Ray;
 use crate::texture::Texture;
 use crate::tools;
@@ -22,6 +23,7 @@ pub struct Hitrec {
     pub v: f64,
     pub front_face: bool, // true: hit outsides
     pub mat: Arc<Material>,
+    pub tangent: Vec3, // direction a fibre runs at p, zero on ordinary surfaces
 }
 
 impl Hitrec {
@@ -34,6 +36,7 @@ impl Hitrec {
             v: 0.0,
             front_face: false,
             mat: nmat,
+            tangent: Vec3::zero(),
         }
     }
     pub fn p(&self) -> Vec3 {
@@ -56,12 +59,32 @@ impl Hitrec {
         self.t = rec.t;
         self.front_face = rec.front_face;
         self.mat = rec.mat.clone();
+        self.tangent = rec.tangent;
     }
 }
 
+// most crossings hit_all walks through before giving up
+pub const MAX_CROSSINGS: usize = 64;
+
 pub trait Hittable: Debug + Send + Sync {
     fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec>;
     fn bebox(&self, t0: f64, t1: f64) -> Option<AABB>;
+    // every crossing in (t_min, t_max) ordered along the ray, found by asking
+    // hit again just past the last one, a step that grows with t
+    fn hit_all(&self, r: Ray, t_min: f64, t_max: f64) -> Vec<Hitrec> {
+        let mut hits: Vec<Hitrec> = Vec::new();
+        let mut t: f64 = t_min;
+        while hits.len() < MAX_CROSSINGS {
+            match self.hit(r, t, t_max) {
+                Some(rec) => {
+                    t = rec.t + 1e-6 * rec.t.abs().max(1.0);
+                    hits.push(rec);
+                }
+                None => break,
+            }
+        }
+        hits
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -659,6 +682,7 @@ pub struct Boxes {
 }
 
 impl Boxes {
+    // the sides at p0 are flipped so every face points out of the box
     pub fn new(p0: Vec3, p1: Vec3, mat: Arc<Material>) -> Self {
         let mut list = Hitlist::new();
 
@@ -670,14 +694,14 @@ impl Boxes {
             p1.z(),
             mat.clone(),
         )));
-        list.add(Arc::new(XyRect::new(
+        list.add(Arc::new(FlipFace::new(Arc::new(XyRect::new(
             p0.x(),
             p1.x(),
             p0.y(),
             p1.y(),
             p0.z(),
             mat.clone(),
-        )));
+        )))));
 
         list.add(Arc::new(XzRect::new(
             p0.x(),
@@ -687,14 +711,14 @@ impl Boxes {
             p1.y(),
             mat.clone(),
         )));
-        list.add(Arc::new(XzRect::new(
+        list.add(Arc::new(FlipFace::new(Arc::new(XzRect::new(
             p0.x(),
             p1.x(),
             p0.z(),
             p1.z(),
             p0.y(),
             mat.clone(),
-        )));
+        )))));
 
         list.add(Arc::new(YzRect::new(
             p0.y(),
@@ -704,14 +728,14 @@ impl Boxes {
             p1.x(),
             mat.clone(),
         )));
-        list.add(Arc::new(YzRect::new(
+        list.add(Arc::new(FlipFace::new(Arc::new(YzRect::new(
             p0.y(),
             p1.y(),
             p0.z(),
             p1.z(),
             p0.x(),
             mat.clone(),
-        )));
+        )))));
 
         Self {
             box_min: p0,
@@ -730,6 +754,29 @@ impl Hittable for Boxes {
     }
 }
 
+// swaps the front and back face of a shape, e.g. so a side of a box faces outwards
+#[derive(Debug, Clone)]
+pub struct FlipFace {
+    shape: Arc<Hittable>,
+}
+
+impl FlipFace {
+    pub fn new(shape: Arc<Hittable>) -> Self {
+        Self { shape }
+    }
+}
+
+impl Hittable for FlipFace {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let mut rec = self.shape.hit(r, t_min, t_max)?;
+        rec.front_face = !rec.front_face;
+        Some(rec)
+    }
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.shape.bebox(t0, t1)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Translate {
     shape: Arc<Hittable>,
@@ -747,12 +794,16 @@ impl Translate {
 
 impl Hittable for Translate {
     fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
-        let moved_r = Ray::new(r.origin() - self.offset(), r.diraction(), r.time());
+        let moved_r = Ray::wnew(
+            r.origin() - self.offset(),
+            r.diraction(),
+            r.time(),
+            r.lambda,
+        );
 
         match self.shape.hit(moved_r.clone(), t_min, t_max) {
             Some(mut rec) => {
                 rec.p += self.offset();
-                rec.set_face(moved_r.clone(), rec.nf());
                 Some(rec)
             }
             None => None,
@@ -840,7 +891,7 @@ impl Hittable for RotateY {
         dir.x = cos * r.diraction().x() - sin * r.diraction().z();
         dir.z = sin * r.diraction().x() + cos * r.diraction().z();
 
-        let ror = Ray::new(org, dir, r.time());
+        let ror = Ray::wnew(org, dir, r.time(), r.lambda);
 
         match self.shape.hit(ror.clone(), t_min, t_max) {
             Some(mut rec) => {
@@ -853,7 +904,8 @@ impl Hittable for RotateY {
                 nf.z = -sin * rec.nf().x() + cos * rec.nf().z();
 
                 rec.p = p;
-                rec.set_face(ror.clone(), nf);
+                // nf already faces the ray, keep front_face from the shape
+                rec.nf = nf;
 
                 Some(rec)
             }
@@ -941,7 +993,7 @@ impl Hittable for RotateX {
         dir.y = cos * r.diraction().y() - sin * r.diraction().z();
         dir.z = sin * r.diraction().y() + cos * r.diraction().z();
 
-        let ror = Ray::new(org, dir, r.time());
+        let ror = Ray::wnew(org, dir, r.time(), r.lambda);
 
         match self.shape.hit(ror.clone(), t_min, t_max) {
             Some(mut rec) => {
@@ -954,7 +1006,8 @@ impl Hittable for RotateX {
                 nf.z = -sin * rec.nf().y() + cos * rec.nf().z();
 
                 rec.p = p;
-                rec.set_face(ror.clone(), nf);
+                // nf already faces the ray, keep front_face from the shape
+                rec.nf = nf;
 
                 Some(rec)
             }
@@ -1042,7 +1095,7 @@ impl Hittable for RotateZ {
         dir.y = cos * r.diraction().y() - sin * r.diraction().x();
         dir.x = sin * r.diraction().y() + cos * r.diraction().x();
 
-        let ror = Ray::new(org, dir, r.time());
+        let ror = Ray::wnew(org, dir, r.time(), r.lambda);
 
         match self.shape.hit(ror.clone(), t_min, t_max) {
             Some(mut rec) => {
@@ -1055,7 +1108,8 @@ impl Hittable for RotateZ {
                 nf.x = -sin * rec.nf().y() + cos * rec.nf().x();
 
                 rec.p = p;
-                rec.set_face(ror.clone(), nf);
+                // nf already faces the ray, keep front_face from the shape
+                rec.nf = nf;
 
                 Some(rec)
             }
@@ -1075,84 +1129,34 @@ impl Hittable for RotateZ {
 #[derive(Debug, Clone)]
 pub struct ConstantMedium {
     boundary: Arc<Hittable>,
-    phase_function: Arc<Material>,
-    neg_inv_density: f64,
+    medium: medium::Homogeneous,
 }
 
 impl ConstantMedium {
     pub fn new(b: Arc<Hittable>, d: f64, a: Arc<Texture>) -> Self {
         Self {
             boundary: b,
-            phase_function: Arc::new(material::Isotropic::new(a)),
-            neg_inv_density: (-1.0 / d),
+            medium: medium::Homogeneous::new(d, a),
         }
     }
     pub fn cnew(b: Arc<Hittable>, d: f64, c: Color) -> Self {
         Self {
             boundary: b,
-            phase_function: Arc::new(material::Isotropic::cnew(c)),
-            neg_inv_density: (-1.0 / d),
+            medium: medium::Homogeneous::cnew(d, c),
+        }
+    }
+    pub fn pnew(b: Arc<Hittable>, d: f64, phase: Arc<Material>) -> Self {
+        Self {
+            boundary: b,
+            medium: medium::Homogeneous::pnew(d, phase),
         }
     }
 }
 
 impl Hittable for ConstantMedium {
     fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
-        const DEBUGABLE: bool = false;
-        let debuging: bool = DEBUGABLE && (tools::randf(0.0, 1.0) < 0.00001);
-
-        match self.boundary.hit(r.clone(), -tools::INF, tools::INF) {
-            Some(mut rec_1) => match self.boundary.hit(r.clone(), rec_1.t + 0.0001, tools::INF) {
-                Some(mut rec_2) => {
-                    if debuging {
-                        print!("\nt_min = {} , t_max = {}\n", rec_1.t, rec_2.t);
-                    }
-
-                    if rec_1.t < t_min {
-                        rec_1.t = t_min;
-                    }
-                    if rec_2.t > t_max {
-                        rec_2.t = t_max;
-                    }
-
-                    if rec_1.t >= rec_2.t {
-                        return None;
-                    }
-
-                    if rec_1.t < 0.0 {
-                        rec_1.t = 0.0;
-                    }
-
-                    let ray_len = r.diraction().length();
-                    let dis_in_boundary = (rec_2.t - rec_1.t) * ray_len;
-                    let hit_dis = self.neg_inv_density * tools::randf(0.0, 1.0).ln();
-
-                    if hit_dis > dis_in_boundary {
-                        return None;
-                    }
-
-                    let mut rec = Hitrec::new(self.phase_function.clone());
-                    rec.t = rec_1.t + hit_dis / ray_len;
-                    rec.p = r.at(rec.t);
-
-                    if debuging {
-                        print!(
-                            "hit_dis = {}\nrec.t = {}\nrec.p = {:?}\n",
-                            hit_dis,
-                            rec.t,
-                            rec.p()
-                        );
-                    }
-
-                    rec.nf = Vec3::new(1.0, 0.0, 0.0);
-                    rec.front_face = true;
-
-                    Some(rec)
-                }
-                None => None,
-            },
-            None => None,
-        }
+        let spans = medium::interior_spans(&self.boundary, &r, t_min, t_max);
+        medium::sample_spans(&self.medium, &r, &spans)
     }
 
     fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
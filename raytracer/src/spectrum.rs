@@ -0,0 +1,294 @@
+use crate::color::Color;
+use crate::tools;
+use std::ops::{Add, AddAssign, Mul};
+
+// visible range used whenever a wavelength is sampled, in nanometers
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+
+// mean of the clamped rgb response over the visible range, so that
+// wavelength_weight averages to white for uniformly sampled wavelengths
+const RGB_MEAN: [f64; 3] = [0.440457, 0.288466, 0.273240];
+// mean of the unclamped rgb response, white balances the film to the equal energy spectrum
+const FILM_WHITE: [f64; 3] = [0.320907, 0.253872, 0.242624];
+// maps an rgb color to weights of the three smooth basis spectra, so that
+// the film sees the original color again after upsampling
+const UPSAMPLE: [[f64; 3]; 3] = [
+    [0.838210, 0.148583, 0.013207],
+    [-0.117852, 1.102767, 0.015086],
+    [-0.022873, 0.011612, 1.011261],
+];
+
+// wavelengths carried by one path, the first is the hero
+pub const N_LAMBDA: usize = 4;
+
+fn lobe(x: f64, mu: f64, s1: f64, s2: f64) -> f64 {
+    let s: f64 = if x < mu { s1 } else { s2 };
+    let t: f64 = (x - mu) / s;
+    (-0.5 * t * t).exp()
+}
+
+// analytic fit of the CIE 1931 color matching functions (Wyman et al. 2013)
+pub fn cie_xyz(lambda: f64) -> Color {
+    let x: f64 = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+    let y: f64 = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+    let z: f64 = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+    Color::new(x, y, z)
+}
+
+// linear sRGB (D65) from XYZ
+pub fn xyz_to_rgb(xyz: Color) -> Color {
+    Color::new(
+        3.2406 * xyz.x() - 1.5372 * xyz.y() - 0.4986 * xyz.z(),
+        -0.9689 * xyz.x() + 1.8758 * xyz.y() + 0.0415 * xyz.z(),
+        0.0557 * xyz.x() - 0.2040 * xyz.y() + 1.0570 * xyz.z(),
+    )
+}
+
+pub fn sample_wavelength() -> f64 {
+    tools::randf(LAMBDA_MIN, LAMBDA_MAX)
+}
+
+// rgb throughput of a path restricted to one uniformly sampled wavelength
+pub fn wavelength_weight(lambda: f64) -> Color {
+    let rgb: Color = xyz_to_rgb(cie_xyz(lambda));
+    Color::new(
+        rgb.x().max(0.0) / RGB_MEAN[0],
+        rgb.y().max(0.0) / RGB_MEAN[1],
+        rgb.z().max(0.0) / RGB_MEAN[2],
+    )
+}
+
+// spectral radiance of a black body at kelvin, lambda in nanometers, arbitrary scale
+pub fn blackbody(lambda: f64, kelvin: f64) -> f64 {
+    const C2: f64 = 1.4387769e7; // h c / k in nm K
+    let l: f64 = lambda / 1000.0;
+    1.0 / (l.powi(5) * ((C2 / (lambda * kelvin)).exp() - 1.0))
+}
+
+// linear rgb of a black body normalized to unit luminance
+pub fn blackbody_rgb(kelvin: f64) -> Color {
+    let mut xyz: Color = Color::zero();
+    let mut lambda: f64 = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        xyz += cie_xyz(lambda) * blackbody(lambda, kelvin);
+        lambda += 5.0;
+    }
+    if xyz.y() <= 0.0 {
+        return Color::zero();
+    }
+    let rgb: Color = xyz_to_rgb(xyz / xyz.y());
+    Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+}
+
+// wavelength dependent index of refraction, lambda in nanometers
+#[derive(Debug, Clone, Copy)]
+pub enum Ior {
+    Constant(f64),
+    Cauchy(f64, f64),              // n = a + b / l^2, l in micrometers
+    Sellmeier([f64; 3], [f64; 3]), // n^2 = 1 + sum b_i l^2 / (l^2 - c_i), l in micrometers
+}
+
+impl Ior {
+    pub fn bk7() -> Self {
+        Ior::Sellmeier(
+            [1.03961212, 0.231792344, 1.01046945],
+            [0.00600069867, 0.0200179144, 103.560653],
+        )
+    }
+    pub fn diamond() -> Self {
+        Ior::Sellmeier(
+            [4.3356, 0.3306, 0.0],
+            [0.1060 * 0.1060, 0.1750 * 0.1750, 0.0],
+        )
+    }
+    pub fn dispersive(&self) -> bool {
+        match self {
+            Ior::Constant(_) => false,
+            _ => true,
+        }
+    }
+    pub fn at(&self, lambda: f64) -> f64 {
+        let l: f64 = lambda / 1000.0;
+        let l2: f64 = l * l;
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy(a, b) => a + b / l2,
+            Ior::Sellmeier(b, c) => {
+                let mut n2: f64 = 1.0;
+                for i in 0..3 {
+                    n2 += b[i] * l2 / (l2 - c[i]);
+                }
+                n2.sqrt()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spectrum {
+    pub v: [f64; N_LAMBDA],
+}
+
+impl Spectrum {
+    pub fn new(x: f64) -> Self {
+        Self { v: [x; N_LAMBDA] }
+    }
+    pub fn max(&self) -> f64 {
+        self.v.iter().cloned().fold(0.0, f64::max)
+    }
+    pub fn elemul(s1: Self, s2: Self) -> Self {
+        let mut v = [0.0; N_LAMBDA];
+        for i in 0..N_LAMBDA {
+            v[i] = s1.v[i] * s2.v[i];
+        }
+        Self { v }
+    }
+}
+
+impl Add for Spectrum {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let mut v = self.v;
+        for i in 0..N_LAMBDA {
+            v[i] += other.v[i];
+        }
+        Self { v }
+    }
+}
+
+impl AddAssign for Spectrum {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Mul<f64> for Spectrum {
+    type Output = Self;
+
+    fn mul(self, other: f64) -> Self {
+        let mut v = self.v;
+        for x in v.iter_mut() {
+            *x *= other;
+        }
+        Self { v }
+    }
+}
+
+// hero wavelength sampling: one uniform wavelength plus evenly rotated companions
+#[derive(Debug, Clone, Copy)]
+pub struct Wavelengths {
+    pub lambda: [f64; N_LAMBDA],
+    basis: [Color; N_LAMBDA],
+    single: bool,
+}
+
+impl Wavelengths {
+    pub fn sample() -> Self {
+        let u: f64 = tools::randf(0.0, 1.0);
+        let mut lambda = [0.0; N_LAMBDA];
+        let mut basis = [Color::zero(); N_LAMBDA];
+        for i in 0..N_LAMBDA {
+            let t: f64 = (u + i as f64 / N_LAMBDA as f64).fract();
+            lambda[i] = LAMBDA_MIN + t * (LAMBDA_MAX - LAMBDA_MIN);
+            basis[i] = Self::basis(lambda[i]);
+        }
+        Self {
+            lambda,
+            basis,
+            single: false,
+        }
+    }
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+    // after wavelength dependent scattering only the hero stays valid
+    pub fn terminate_secondary(&mut self) {
+        self.single = true;
+    }
+
+    // clamped rgb response split into a partition of unity over the visible range
+    fn basis(lambda: f64) -> Color {
+        let rgb: Color = xyz_to_rgb(cie_xyz(lambda));
+        let c: Color = Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0));
+        let sum: f64 = c.x() + c.y() + c.z();
+        if sum <= 0.0 {
+            Color::ones() / 3.0
+        } else {
+            c / sum
+        }
+    }
+
+    // smooth spectrum of an rgb reflectance or emission, white maps to a flat 1
+    pub fn upsample(&self, c: Color) -> Spectrum {
+        let w = Color::new(
+            UPSAMPLE[0][0] * c.x() + UPSAMPLE[0][1] * c.y() + UPSAMPLE[0][2] * c.z(),
+            UPSAMPLE[1][0] * c.x() + UPSAMPLE[1][1] * c.y() + UPSAMPLE[1][2] * c.z(),
+            UPSAMPLE[2][0] * c.x() + UPSAMPLE[2][1] * c.y() + UPSAMPLE[2][2] * c.z(),
+        );
+        let mut v = [0.0; N_LAMBDA];
+        for i in 0..N_LAMBDA {
+            v[i] = (w * self.basis[i]).max(0.0);
+        }
+        Spectrum { v }
+    }
+
+    // project the sampled radiance through the CIE curves and back to linear sRGB
+    pub fn to_rgb(&self, s: &Spectrum) -> Color {
+        let mut xyz: Color = Color::zero();
+        if self.single {
+            xyz = cie_xyz(self.lambda[0]) * s.v[0];
+        } else {
+            for i in 0..N_LAMBDA {
+                xyz += cie_xyz(self.lambda[i]) * (s.v[i] / N_LAMBDA as f64);
+            }
+        }
+        let rgb: Color = xyz_to_rgb(xyz);
+        Color::new(
+            rgb.x() / FILM_WHITE[0],
+            rgb.y() / FILM_WHITE[1],
+            rgb.z() / FILM_WHITE[2],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_white_round_trip() {
+        let n = 20000;
+        let mut sum = Color::zero();
+        for _ in 0..n {
+            let wl = Wavelengths::sample();
+            let s: Spectrum = wl.upsample(Color::ones());
+            // white upsamples to a flat spectrum
+            for v in s.v.iter() {
+                assert!((v - 1.0).abs() < 1e-3);
+            }
+            sum += wl.to_rgb(&s);
+        }
+        let rgb: Color = sum / n as f64;
+        assert!((rgb.x() - 1.0).abs() < 0.02);
+        assert!((rgb.y() - 1.0).abs() < 0.02);
+        assert!((rgb.z() - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_wavelength_weight() {
+        // a single uniform wavelength is white on average too
+        let n = 100000;
+        let mut sum = Color::zero();
+        for i in 0..n {
+            let t: f64 = (i as f64 + 0.5) / n as f64;
+            sum += wavelength_weight(LAMBDA_MIN + t * (LAMBDA_MAX - LAMBDA_MIN));
+        }
+        let rgb: Color = sum / n as f64;
+        assert!((rgb.x() - 1.0).abs() < 1e-3);
+        assert!((rgb.y() - 1.0).abs() < 1e-3);
+        assert!((rgb.z() - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,9 @@
+use crate::material::Lamber;
+use crate::material::Material;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// plain diffuse material for the shape tests
+pub fn gray() -> Arc<Material> {
+    Arc::new(Lamber::cnew(Vec3::new(0.5, 0.5, 0.5)))
+}
@@ -28,3 +28,101 @@ pub fn clamp(x: f64, x_min: f64, x_max: f64) -> f64 {
     }
     return x;
 }
+
+// real roots of a x^2 + b x + c = 0 in ascending order
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return Vec::new();
+        }
+        return vec![-c / b];
+    }
+    let dis: f64 = b * b - 4.0 * a * c;
+    if dis < 0.0 {
+        return Vec::new();
+    }
+    // avoids cancellation between -b and the root
+    let q: f64 = -0.5 * (b + b.signum() * dis.sqrt());
+    if q == 0.0 {
+        return vec![0.0, 0.0];
+    }
+    let (x0, x1) = (q / a, c / q);
+    if x0 < x1 {
+        vec![x0, x1]
+    } else {
+        vec![x1, x0]
+    }
+}
+
+// real roots of x^3 + a x^2 + b x + c = 0 in ascending order
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let q: f64 = (a * a - 3.0 * b) / 9.0;
+    let r: f64 = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    let shift: f64 = a / 3.0;
+    if r * r < q * q * q {
+        // three real roots, the trigonometric form
+        let theta: f64 = clamp(r / (q * q * q).sqrt(), -1.0, 1.0).acos();
+        let m: f64 = -2.0 * q.sqrt();
+        let mut roots = vec![
+            m * (theta / 3.0).cos() - shift,
+            m * ((theta + 2.0 * PI) / 3.0).cos() - shift,
+            m * ((theta - 2.0 * PI) / 3.0).cos() - shift,
+        ];
+        roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+        roots
+    } else {
+        let s: f64 = -r.signum() * (r.abs() + (r * r - q * q * q).sqrt()).cbrt();
+        let t: f64 = if s == 0.0 { 0.0 } else { q / s };
+        vec![s + t - shift]
+    }
+}
+
+// real roots of x^4 + a x^3 + b x^2 + c x + d = 0 in ascending order, by
+// Ferrari's method with each root polished by a few newton steps
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // depressed quartic y^4 + p y^2 + q y + r with x = y - a / 4
+    let a2: f64 = a * a;
+    let p: f64 = b - 3.0 * a2 / 8.0;
+    let q: f64 = c - a * b / 2.0 + a2 * a / 8.0;
+    let r: f64 = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+
+    let mut ys: Vec<f64> = Vec::new();
+    if q.abs() < 1e-12 {
+        // biquadratic in y^2
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                ys.push(z.sqrt());
+                ys.push(-z.sqrt());
+            }
+        }
+    } else {
+        // the resolvent cubic always has a positive root when q is not 0
+        let m: f64 = *solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0)
+            .last()
+            .unwrap();
+        if m <= 0.0 {
+            return Vec::new();
+        }
+        let s: f64 = (2.0 * m).sqrt();
+        ys.extend(solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s)));
+        ys.extend(solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)));
+    }
+
+    let mut roots: Vec<f64> = ys
+        .iter()
+        .map(|y| {
+            let mut x: f64 = y - a / 4.0;
+            for _ in 0..3 {
+                let f: f64 = (((x + a) * x + b) * x + c) * x + d;
+                let df: f64 = ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+                if df == 0.0 {
+                    break;
+                }
+                x -= f / df;
+            }
+            x
+        })
+        .collect();
+    roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    roots
+}
@@ -0,0 +1,634 @@
+use crate::ray::Ray;
+use crate::shapes::Hitrec;
+use crate::shapes::Hittable;
+use crate::shapes::AABB;
+use crate::tools;
+use crate::vec3::Vec3;
+use std::ops::Mul;
+use std::sync::Arc;
+
+// row major 4x4 matrix acting on column vectors
+#[derive(Clone, Debug, PartialEq, Copy)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Self {
+        Self { m }
+    }
+    pub fn identity() -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Self { m }
+    }
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                m[i][j] = self.m[j][i];
+            }
+        }
+        Self { m }
+    }
+
+    // gauss-jordan elimination with partial pivoting, None when singular
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            let mut pivot = col;
+            for row in (col + 1)..4 {
+                if a[row][col].abs() > a[pivot][col].abs() {
+                    pivot = row;
+                }
+            }
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let d: f64 = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= d;
+                inv[col][j] /= d;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f: f64 = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Self { m: inv })
+    }
+
+    pub fn point(&self, p: &Vec3) -> Vec3 {
+        let m = &self.m;
+        let x: f64 = m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3];
+        let y: f64 = m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3];
+        let z: f64 = m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3];
+        let w: f64 = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+        if w == 1.0 || w == 0.0 {
+            Vec3::new(x, y, z)
+        } else {
+            Vec3::new(x / w, y / w, z / w)
+        }
+    }
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                for k in 0..4 {
+                    m[i][j] += self.m[i][k] * other.m[k][j];
+                }
+            }
+        }
+        Self { m }
+    }
+}
+
+// an affine transform kept together with its inverse
+#[derive(Clone, Debug, PartialEq, Copy)]
+pub struct Transform {
+    pub m: Mat4,
+    pub inv: Mat4,
+}
+
+impl Transform {
+    pub fn new(m: Mat4) -> Self {
+        Self {
+            m,
+            inv: m.inverse().expect("singular transform"),
+        }
+    }
+    pub fn identity() -> Self {
+        Self {
+            m: Mat4::identity(),
+            inv: Mat4::identity(),
+        }
+    }
+    pub fn translate(d: Vec3) -> Self {
+        let mut m = Mat4::identity();
+        let mut inv = Mat4::identity();
+        for (i, x) in [d.x(), d.y(), d.z()].iter().enumerate() {
+            m.m[i][3] = *x;
+            inv.m[i][3] = -*x;
+        }
+        Self { m, inv }
+    }
+    pub fn scale(s: Vec3) -> Self {
+        let mut m = Mat4::identity();
+        let mut inv = Mat4::identity();
+        for (i, x) in [s.x(), s.y(), s.z()].iter().enumerate() {
+            m.m[i][i] = *x;
+            inv.m[i][i] = 1.0 / *x;
+        }
+        Self { m, inv }
+    }
+    // counterclockwise around a unit axis when looking down at it, angle in degrees
+    pub fn rotate(axis: Vec3, angle: f64) -> Self {
+        let a: Vec3 = axis.unit();
+        let (s, c) = tools::dtr(angle).sin_cos();
+        let t: f64 = 1.0 - c;
+        let m = Mat4::new([
+            [
+                t * a.x() * a.x() + c,
+                t * a.x() * a.y() - s * a.z(),
+                t * a.x() * a.z() + s * a.y(),
+                0.0,
+            ],
+            [
+                t * a.x() * a.y() + s * a.z(),
+                t * a.y() * a.y() + c,
+                t * a.y() * a.z() - s * a.x(),
+                0.0,
+            ],
+            [
+                t * a.x() * a.z() - s * a.y(),
+                t * a.y() * a.z() + s * a.x(),
+                t * a.z() * a.z() + c,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        // rotations are orthogonal
+        Self {
+            m,
+            inv: m.transpose(),
+        }
+    }
+    pub fn rotate_x(angle: f64) -> Self {
+        Self::rotate(Vec3::new(1.0, 0.0, 0.0), angle)
+    }
+    pub fn rotate_y(angle: f64) -> Self {
+        Self::rotate(Vec3::new(0.0, 1.0, 0.0), angle)
+    }
+    pub fn rotate_z(angle: f64) -> Self {
+        Self::rotate(Vec3::new(0.0, 0.0, 1.0), angle)
+    }
+    // each coordinate gains the others times the matching factor, e.g. x += xy * y
+    pub fn shear(xy: f64, xz: f64, yx: f64, yz: f64, zx: f64, zy: f64) -> Self {
+        Self::new(Mat4::new([
+            [1.0, xy, xz, 0.0],
+            [yx, 1.0, yz, 0.0],
+            [zx, zy, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]))
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            m: self.inv,
+            inv: self.m,
+        }
+    }
+    // self first, then other
+    pub fn then(&self, other: &Self) -> Self {
+        *other * *self
+    }
+
+    pub fn point(&self, p: &Vec3) -> Vec3 {
+        self.m.point(p)
+    }
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        self.m.vector(v)
+    }
+    // normals go through the inverse transpose to stay perpendicular to the surface
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        self.inv.transpose().vector(n)
+    }
+    // the direction is not normalized, so t means the same on both sides
+    pub fn ray(&self, r: &Ray) -> Ray {
+        Ray::wnew(
+            self.point(&r.origin()),
+            self.vector(&r.diraction()),
+            r.time(),
+            r.lambda,
+        )
+    }
+    pub fn bbox(&self, b: &AABB) -> AABB {
+        let corners: Vec<Vec3> = (0..8).map(|i| self.point(&corner(b, i))).collect();
+        bound(&corners, 0.0)
+    }
+}
+
+// the i-th of the 8 corners of a box, one bit per axis
+fn corner(b: &AABB, i: usize) -> Vec3 {
+    Vec3::new(
+        if i & 1 == 0 { b.min().x() } else { b.max().x() },
+        if i & 2 == 0 { b.min().y() } else { b.max().y() },
+        if i & 4 == 0 { b.min().z() } else { b.max().z() },
+    )
+}
+
+// smallest box around the points, grown by pad on every side
+fn bound(points: &[Vec3], pad: f64) -> AABB {
+    let mut mini = Vec3::ones() * tools::INF;
+    let mut maxi = Vec3::ones() * -tools::INF;
+    for p in points.iter() {
+        mini = Vec3::new(
+            mini.x().min(p.x()),
+            mini.y().min(p.y()),
+            mini.z().min(p.z()),
+        );
+        maxi = Vec3::new(
+            maxi.x().max(p.x()),
+            maxi.y().max(p.y()),
+            maxi.z().max(p.z()),
+        );
+    }
+    AABB::new(mini - Vec3::ones() * pad, maxi + Vec3::ones() * pad)
+}
+
+// applying a * b applies b first
+impl Mul for Transform {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self {
+            m: self.m * other.m,
+            inv: other.inv * self.inv,
+        }
+    }
+}
+
+// a shape placed in the world by an arbitrary affine transform, replacing
+// chains of Translate and Rotate with one instance
+#[derive(Debug, Clone)]
+pub struct Transformed {
+    shape: Arc<Hittable>,
+    tf: Transform,
+}
+
+impl Transformed {
+    pub fn new(shape: Arc<Hittable>, tf: Transform) -> Self {
+        Self { shape, tf }
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let local: Ray = self.tf.inverse().ray(&r);
+        let mut rec = self.shape.hit(local, t_min, t_max)?;
+        rec.p = self.tf.point(&rec.p);
+        // nf already faces the ray and keeps doing so, front_face comes from the shape
+        rec.nf = self.tf.normal(&rec.nf).unit();
+        rec.tangent = self.tf.vector(&rec.tangent);
+        Some(rec)
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let b = self.shape.bebox(t0, t1)?;
+        Some(self.tf.bbox(&b))
+    }
+}
+
+// unit quaternion, rotations interpolated with it take the shortest way round
+#[derive(Clone, Debug, PartialEq, Copy)]
+pub struct Quat {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Quat {
+    pub fn new(w: f64, v: Vec3) -> Self {
+        Self { w, v }
+    }
+    pub fn identity() -> Self {
+        Self::new(1.0, Vec3::zero())
+    }
+    // same convention as Transform::rotate, angle in degrees
+    pub fn rotation(axis: Vec3, angle: f64) -> Self {
+        let (s, c) = (tools::dtr(angle) / 2.0).sin_cos();
+        Self::new(c, axis.unit() * s)
+    }
+    pub fn dot(&self, other: &Self) -> f64 {
+        self.w * other.w + self.v * other.v
+    }
+    pub fn unit(&self) -> Self {
+        let len: f64 = self.dot(self).sqrt();
+        Self::new(self.w / len, self.v / len)
+    }
+
+    pub fn slerp(a: &Self, b: &Self, t: f64) -> Self {
+        let mut b: Self = *b;
+        let mut cos_t: f64 = a.dot(&b);
+        if cos_t < 0.0 {
+            b = Self::new(-b.w, -b.v);
+            cos_t = -cos_t;
+        }
+        if cos_t > 0.9995 {
+            // nearly parallel, a normalized lerp is indistinguishable
+            return Self::new(a.w + (b.w - a.w) * t, a.v + (b.v - a.v) * t).unit();
+        }
+        let theta: f64 = cos_t.acos();
+        let wa: f64 = ((1.0 - t) * theta).sin() / theta.sin();
+        let wb: f64 = (t * theta).sin() / theta.sin();
+        Self::new(a.w * wa + b.w * wb, a.v * wa + b.v * wb)
+    }
+
+    pub fn to_transform(&self) -> Transform {
+        let q: Self = self.unit();
+        let (w, x, y, z) = (q.w, q.v.x(), q.v.y(), q.v.z());
+        let m = Mat4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Transform {
+            m,
+            inv: m.transpose(),
+        }
+    }
+}
+
+// the pose of an instance at one moment: scaled, then rotated, then translated
+#[derive(Clone, Debug, PartialEq, Copy)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(time: f64, translation: Vec3, rotation: Quat, scale: Vec3) -> Self {
+        Self {
+            time,
+            translation,
+            rotation,
+            scale,
+        }
+    }
+    // a rigid pose without scaling
+    pub fn rnew(time: f64, translation: Vec3, rotation: Quat) -> Self {
+        Self::new(time, translation, rotation, Vec3::ones())
+    }
+
+    pub fn to_transform(&self) -> Transform {
+        Transform::scale(self.scale)
+            .then(&self.rotation.to_transform())
+            .then(&Transform::translate(self.translation))
+    }
+}
+
+// keyframes sorted by time, the pose holds before the first and after the last
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    pub keys: Vec<Keyframe>,
+}
+
+impl AnimatedTransform {
+    pub fn new(keys: Vec<Keyframe>) -> Self {
+        assert!(!keys.is_empty(), "an animation needs at least one keyframe");
+        let mut keys = keys;
+        keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        Self { keys }
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        let first: &Keyframe = &self.keys[0];
+        let last: &Keyframe = self.keys.last().unwrap();
+        if time <= first.time {
+            return first.to_transform();
+        }
+        if time >= last.time {
+            return last.to_transform();
+        }
+        let mut i = 0;
+        while self.keys[i + 1].time < time {
+            i += 1;
+        }
+        let (a, b) = (&self.keys[i], &self.keys[i + 1]);
+        let t: f64 = (time - a.time) / (b.time - a.time);
+        Keyframe::new(
+            time,
+            a.translation + (b.translation - a.translation) * t,
+            Quat::slerp(&a.rotation, &b.rotation, t),
+            a.scale + (b.scale - a.scale) * t,
+        )
+        .to_transform()
+    }
+
+    // bounds of a box carried through the animation from t0 to t1; the
+    // corners move on curves, so each span between keyframes is sampled and
+    // the box padded by half the longest step a corner takes, which covers
+    // the path between two samples
+    pub fn bbox(&self, b: &AABB, t0: f64, t1: f64) -> AABB {
+        let mut times: Vec<f64> = vec![t0];
+        for k in self.keys.iter() {
+            if k.time > t0 && k.time < t1 {
+                times.push(k.time);
+            }
+        }
+        times.push(t1);
+
+        let mut points: Vec<Vec3> = Vec::new();
+        let mut prev: Vec<Vec3> = Vec::new();
+        let mut pad: f64 = 0.0;
+        for span in times.windows(2) {
+            for s in 0..=BOUND_STEPS {
+                let time: f64 = span[0] + (span[1] - span[0]) * s as f64 / BOUND_STEPS as f64;
+                let tf: Transform = self.at(time);
+                let now: Vec<Vec3> = (0..8).map(|i| tf.point(&corner(b, i))).collect();
+                for (p, q) in prev.iter().zip(now.iter()) {
+                    pad = pad.max((*q - *p).length() / 2.0);
+                }
+                points.extend(now.iter());
+                prev = now;
+            }
+        }
+        bound(&points, pad)
+    }
+}
+
+const BOUND_STEPS: usize = 16;
+
+// a Transformed whose pose follows the animation at each ray's time, so
+// anything placed with it blurs over the shutter
+#[derive(Debug, Clone)]
+pub struct Animated {
+    shape: Arc<Hittable>,
+    anim: AnimatedTransform,
+}
+
+impl Animated {
+    pub fn new(shape: Arc<Hittable>, anim: AnimatedTransform) -> Self {
+        Self { shape, anim }
+    }
+}
+
+impl Hittable for Animated {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<Hitrec> {
+        let tf: Transform = self.anim.at(r.time());
+        let mut rec = self.shape.hit(tf.inverse().ray(&r), t_min, t_max)?;
+        rec.p = tf.point(&rec.p);
+        rec.nf = tf.normal(&rec.nf).unit();
+        rec.tangent = tf.vector(&rec.tangent);
+        Some(rec)
+    }
+
+    fn bebox(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let b = self.shape.bebox(t0, t1)?;
+        Some(self.anim.bbox(&b, t0, t1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lamber;
+    use crate::shapes;
+    use crate::shapes::Sphere;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn test_inverse() {
+        let tf = Transform::rotate(Vec3::new(1.0, 2.0, 3.0), 40.0)
+            .then(&Transform::scale(Vec3::new(2.0, 0.5, 3.0)))
+            .then(&Transform::shear(0.3, 0.0, 0.0, -0.2, 0.1, 0.0))
+            .then(&Transform::translate(Vec3::new(1.0, -4.0, 2.0)));
+        let gj = Transform::new(tf.m);
+        let p = Vec3::new(0.7, -1.3, 2.2);
+        assert!(close(tf.inverse().point(&tf.point(&p)), p));
+        assert!(close(gj.inverse().point(&tf.point(&p)), p));
+        assert!(Mat4::new([[1.0; 4]; 4]).inverse().is_none());
+    }
+
+    #[test]
+    fn test_normal() {
+        // a plane sheared along x keeps its transformed normal perpendicular to it
+        let tf = Transform::shear(0.0, 0.0, 0.8, 0.0, 0.0, 0.0)
+            .then(&Transform::scale(Vec3::new(3.0, 1.0, 0.5)));
+        let n: Vec3 = tf.normal(&Vec3::new(0.0, 1.0, 0.0));
+        for v in [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)].iter() {
+            assert!((tf.vector(v) * n).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_matches_instances() {
+        let mat = Arc::new(Lamber::cnew(Vec3::new(0.5, 0.5, 0.5)));
+        let shape = Arc::new(shapes::Boxes::new(Vec3::zero(), Vec3::ones(), mat));
+        let old = shapes::Translate::new(
+            Arc::new(shapes::RotateY::new(shape.clone(), 30.0)),
+            Vec3::new(2.0, 0.0, 1.0),
+        );
+        let new = Transformed::new(
+            shape,
+            Transform::rotate_y(30.0).then(&Transform::translate(Vec3::new(2.0, 0.0, 1.0))),
+        );
+        let r = Ray::new(Vec3::new(-3.0, 0.4, -2.0), Vec3::new(5.2, 0.1, 3.6), 0.0);
+        let a = old.hit(r, 0.001, tools::INF).unwrap();
+        let b = new.hit(r, 0.001, tools::INF).unwrap();
+        assert!((a.t - b.t).abs() < 1e-9);
+        assert!(close(a.p, b.p) && close(a.nf, b.nf));
+        assert_eq!(a.front_face, b.front_face);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mat = Arc::new(Lamber::cnew(Vec3::new(0.5, 0.5, 0.5)));
+        let ball = Arc::new(Sphere::new(Vec3::zero(), 1.0, mat));
+        let tf = Transform::scale(Vec3::new(2.0, 1.0, 1.0))
+            .then(&Transform::rotate_z(45.0))
+            .then(&Transform::translate(Vec3::new(0.0, 5.0, 0.0)));
+        let b = Transformed::new(ball, tf).bebox(0.0, 1.0).unwrap();
+        // every point of the ellipsoid lies inside the transformed box
+        for i in 0..64 {
+            let phi: f64 = i as f64 * 0.7;
+            let cos_t: f64 = 1.0 - 2.0 * (i as f64 + 0.5) / 64.0;
+            let sin_t: f64 = (1.0 - cos_t * cos_t).sqrt();
+            let p: Vec3 = tf.point(&Vec3::new(sin_t * phi.cos(), sin_t * phi.sin(), cos_t));
+            assert!(p.x() >= b.min().x() && p.x() <= b.max().x());
+            assert!(p.y() >= b.min().y() && p.y() <= b.max().y());
+            assert!(p.z() >= b.min().z() && p.z() <= b.max().z());
+        }
+    }
+
+    #[test]
+    fn test_quat() {
+        let axis = Vec3::new(1.0, -2.0, 0.5);
+        let p = Vec3::new(0.3, 1.1, -0.8);
+        let q: Transform = Quat::rotation(axis, 70.0).to_transform();
+        assert!(close(q.point(&p), Transform::rotate(axis, 70.0).point(&p)));
+        // halfway between 10 and 150 degrees about the same axis is 80
+        let a = Quat::rotation(axis, 10.0);
+        let b = Quat::rotation(axis, 150.0);
+        let mid: Transform = Quat::slerp(&a, &b, 0.5).to_transform();
+        assert!(close(
+            mid.point(&p),
+            Transform::rotate(axis, 80.0).point(&p)
+        ));
+    }
+
+    #[test]
+    fn test_animated_bounds() {
+        let mat = Arc::new(Lamber::cnew(Vec3::new(0.5, 0.5, 0.5)));
+        let shape = Arc::new(shapes::Boxes::new(
+            Vec3::zero(),
+            Vec3::new(1.0, 3.0, 1.0),
+            mat,
+        ));
+        let anim = AnimatedTransform::new(vec![
+            Keyframe::rnew(0.0, Vec3::zero(), Quat::identity()),
+            Keyframe::new(
+                0.6,
+                Vec3::new(2.0, 0.0, 0.0),
+                Quat::rotation(Vec3::new(0.0, 0.0, 1.0), 170.0),
+                Vec3::new(1.0, 0.5, 2.0),
+            ),
+        ]);
+        let inner = shape.bebox(0.0, 1.0).unwrap();
+        let b = Animated::new(shape, anim.clone()).bebox(0.0, 1.0).unwrap();
+        for i in 0..=200 {
+            let tf: Transform = anim.at(i as f64 / 200.0);
+            for c in 0..8 {
+                let p: Vec3 = tf.point(&corner(&inner, c));
+                assert!(p.x() >= b.min().x() && p.x() <= b.max().x());
+                assert!(p.y() >= b.min().y() && p.y() <= b.max().y());
+                assert!(p.z() >= b.min().z() && p.z() <= b.max().z());
+            }
+        }
+        // the pose holds after the last keyframe
+        assert_eq!(anim.at(0.6), anim.at(0.9));
+    }
+}
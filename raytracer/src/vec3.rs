@@ -237,6 +237,31 @@ impl Neg for Vec3 {
     }
 }
 
+// orthonormal basis around w, used to turn local samples into world directions
+#[derive(Clone, Debug, PartialEq, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn new(n: Vec3) -> Self {
+        let w: Vec3 = n.unit();
+        let a: Vec3 = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v: Vec3 = Vec3::cross(w, a).unit();
+        let u: Vec3 = Vec3::cross(w, v);
+        Self { u, v, w }
+    }
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        self.u * a.x() + self.v * a.y() + self.w * a.z()
+    }
+}
+
 pub fn rand_in_unit_sphere() -> Vec3 {
     loop {
         let p: Vec3 = Vec3::randvr(-1.0, 1.0);